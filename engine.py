@@ -2,6 +2,12 @@ import subprocess
 import json
 import tpn
 
+class EngineError(Exception):
+    def __init__(self, message):
+        super().__init__(f"{message['code']}: {message['message']}")
+        self.code = message["code"]
+        self.line = message["line"]
+
 class Engine:
     def __init__(self, path):
         self.process = subprocess.Popen([path],
@@ -18,10 +24,20 @@ class Engine:
         while True:
             raw_message = self.process.stdout.readline()
             try:
-                return json.loads(raw_message)
+                message = json.loads(raw_message)
             except:
                 assert False, "Unknown message:" + raw_message
 
+            if message["type"] == "Error":
+                raise EngineError(message)
+
+            return message
+
+    def hello(self):
+        msg = { "type": "Hello" }
+        self.send_message(msg)
+        return self.receive_message()
+
     def load(self, input_nodes, output_nodes, node_evals):
         msg = {
             "type": "Load",
@@ -32,22 +48,51 @@ class Engine:
 
         self.send_message(msg)
 
-    def play_game(self):
-        msg = { "type": "PlayGame" }
+    def seed(self, seed):
+        msg = { "type": "Seed", "seed": seed }
+        self.send_message(msg)
+
+    def play_game(self, seed=None):
+        msg = { "type": "PlayGame", "seed": seed }
         self.send_message(msg)
         response = self.receive_message()
         return response["score"]
 
+    def play_games(self, games=None, seeds=None, fumen=False):
+        """With fumen set, the answer also has every game as a fumen in
+        "fumens", a page per placement."""
+        msg = { "type": "PlayGame", "games": games, "seeds": seeds, "fumen": fumen }
+        self.send_message(msg)
+        return self.receive_message()
+
+    def play_match(self, nets, games=None, seeds=None):
+        """Plays versus matches between two networks, each given as a dict
+        with input_nodes, output_nodes and node_evals like load takes."""
+        msg = { "type": "Match", "nets": nets, "games": games, "seeds": seeds }
+        self.send_message(msg)
+        return self.receive_message()
+
     def pos(self, tpn):
         msg = { "type": "Pos", "tpn": tpn }
         self.send_message(msg)
 
+    def pos_fumen(self, fumen):
+        """Sets the position from the first page of a fumen string or URL,
+        pieces coming from a quiz comment such as #Q=[I](T)SZLO."""
+        msg = { "type": "Pos", "fumen": fumen }
+        self.send_message(msg)
+
     def go(self):
         msg = { "type": "Go" }
         self.send_message(msg)
         return self.receive_message()
 
     
+    def peek_fumen(self):
+        msg = { "type": "Peek", "fumen": True }
+        self.send_message(msg)
+        return self.receive_message()["fumen"]
+
     def peek(self):
         msg = { "type": "Peek" }
         self.send_message(msg)
@@ -55,6 +100,15 @@ class Engine:
         print(out)
         return tpn.loads(out["tpn"])
 
+    def set_option(self, name, value):
+        msg = { "type": "SetOption", "name": name, "value": value }
+        self.send_message(msg)
+
+    def options(self):
+        msg = { "type": "Options" }
+        self.send_message(msg)
+        return self.receive_message()["options"]
+
     def ready(self):
         msg = { "type": "Ready" }
         self.send_message(msg)
@@ -124,10 +124,16 @@ class Graphic():
         self.fps = fps
         self.board = board
         self.current_piece = -1
+        self.hold_piece = 0
         self.next_pieces = []
         self.action_list = []
+        # Cells of the piece at its spawn and after each action, as sent
+        # by the engine with its rotation system and kicks applied
+        self.cells = None
         self.score = 0
         self.num_columns, self.num_rows = board.shape[1], board.shape[0]
+        # The visible field is at most 20 rows, pieces spawn just above it
+        self.hidden_rows = max(self.num_rows - 20, 2)
         self.width = width
         self.height = self.width * self.num_rows / self.num_columns
         self.side_panel_cols = 6
@@ -146,7 +152,8 @@ class Graphic():
             4 : (239, 121, 33),
             5 : (66, 182, 66),
             6 : (173, 77, 156),
-            7 : (239, 32, 41)
+            7 : (239, 32, 41),
+            8 : (153, 153, 153)
         }
 
     def fill_gradient(self, surface, color, gradient, rect=None, vertical=True, forward=True):
@@ -190,10 +197,10 @@ class Graphic():
 
 
     def draw_grid(self):
-        for row in range(2, self.num_rows+1):
+        for row in range(self.hidden_rows, self.num_rows+1):
             pg.draw.line(self.display, self.grid_color, (0, row * self.block_height), (self.width, row * self.block_height), 2)
         for col in range(self.num_columns+1):
-            pg.draw.line(self.display, self.grid_color, (col * self.block_width, 2*self.block_height), (col * self.block_width, self.height), 2)
+            pg.draw.line(self.display, self.grid_color, (col * self.block_width, self.hidden_rows*self.block_height), (col * self.block_width, self.height), 2)
 
     def draw_board(self):
         for row in range(self.num_rows):
@@ -222,6 +229,36 @@ class Graphic():
                     pg.draw.rect(self.display, color, ((col+j) * self.block_width, (row+i) * self.block_height, self.block_width, self.block_height), 0)
         
 
+    def draw_cells(self, piece, cells):
+        """Draws a piece covering the given (column, row) cells"""
+        color = self.ID_2_RGB[piece]
+        for col, row in cells:
+            pg.draw.rect(self.display, color, (col * self.block_width, row * self.block_height, self.block_width, self.block_height), 0)
+
+    def draw_moving_piece(self, step, pos, rotation):
+        """Draws the current piece after the given number of actions, from
+        the engine cells when there are some
+        """
+        if self.cells:
+            self.draw_cells(self.current_piece, self.cells[step])
+        else:
+            self.draw_piece(self.current_piece, pos, rotation=rotation)
+
+    def fits(self, piece, pos, rotation):
+        """Checks that a piece is inside the board and does not overlap it
+
+        Args:
+            piece (int): piece to check
+            pos (tuple): row and column of the piece
+            rotation (int): rotation of the piece
+        """
+        row, col = pos
+        p = PIECES[piece-1]
+        p = p[rotation%len(p)]
+        if row < 0 or col < 0 or row + p.shape[0] > self.num_rows or col + p.shape[1] > self.num_columns:
+            return False
+        return not np.any((p != 0) & (self.board[row:row+p.shape[0], col:col+p.shape[1]] != 0))
+
     def draw_side_panel_pieces(self):
         """Draws current piece and next pieces on the side panel
         """
@@ -238,25 +275,50 @@ class Graphic():
         self.display.blit(score_text, pos)
         
 
-    def animate_piece(self):
-        """Animates the piece falling down the board
+    def spawn(self, piece):
+        """Column and row where a piece spawns, in the middle of the board
+        just above the visible field
+        """
+        x_offset, y_offset = SPAWNS[piece-1]
+        x_offset += int((self.num_columns - 10) / 2)
+        y_offset += self.hidden_rows - 2
+        return x_offset, y_offset
 
-        Args:
-            action_list (list): list of actions to perform
+    def animate_piece(self):
+        """Animates the piece through action_list. Without engine cells
+        the actions are replayed with classic rotation, which has no kicks.
         """
-        x_offset, y_offset = SPAWNS[self.current_piece-1]
+        x_offset, y_offset = self.spawn(self.current_piece)
         rot = 0
-        self.draw_piece(self.current_piece, (y_offset, x_offset), rotation=rot)
+        self.draw_moving_piece(0, (y_offset, x_offset), rot)
         pg.display.update()
-        for action in self.action_list:
+        for step, action in enumerate(self.action_list, 1):
             if action == "SoftDrop":
                 self.clock.tick(self.fps)
                 self.clock.tick(self.fps)
                 y_offset += 1
+            elif action == "HardDrop":
+                while self.fits(self.current_piece, (y_offset + 1, x_offset), rot):
+                    y_offset += 1
+            elif action == "DasLeft":
+                while self.fits(self.current_piece, (y_offset, x_offset - 1), rot):
+                    x_offset -= 1
+            elif action == "DasRight":
+                while self.fits(self.current_piece, (y_offset, x_offset + 1), rot):
+                    x_offset += 1
             elif action == "MoveLeft":
                 x_offset -= 1
             elif action == "MoveRight":
                 x_offset += 1
+            elif action == "Hold":
+                # An empty hold slot takes the next piece instead
+                held = self.hold_piece
+                self.hold_piece = self.current_piece
+                if held == 0:
+                    held, self.next_pieces = self.next_pieces[0], self.next_pieces[1:]
+                self.current_piece = held
+                x_offset, y_offset = self.spawn(self.current_piece)
+                rot = 0
             elif action == "RotateCounterclockwise":
                 rot_num = len(ROTATION_TABLE[self.current_piece - 1])
                 offsets = ROTATION_TABLE[self.current_piece - 1][rot - 1]
@@ -273,7 +335,7 @@ class Graphic():
                 assert False, "Unknown action!"
             
             self.fill_gradient(self.display, self.bg_color_1, self.bg_color_2, vertical=False, forward=True)
-            self.draw_piece(self.current_piece, (y_offset, x_offset), rotation=rot)
+            self.draw_moving_piece(step, (y_offset, x_offset), rot)
             self.draw_board()
             self.draw_grid()
             self.show_score()
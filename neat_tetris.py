@@ -146,10 +146,12 @@ if __name__ == "__main__":
             action_list = move["action_list"]
 
             graphic.action_list = action_list
+            graphic.cells = move.get("cells")
 
             graphic.board = np.array(pos.board)
             graphic.current_piece = pos.current_piece
-            graphic.next_pieces = [pos.next_piece]
+            graphic.hold_piece = pos.hold_piece
+            graphic.next_pieces = pos.queue
             graphic.score = pos.score
 
             graphic.tick()
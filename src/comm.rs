@@ -1,57 +1,505 @@
-use std::{io, str::FromStr, time::Instant};
+use std::{fmt, io, str::FromStr, time::Instant};
 
 use serde::{Deserialize, Serialize};
-use lazy_static::lazy_static;
-use std::sync::Mutex;
 
 use crate::{
-    net::FeedForwardNetwork,
-    pos::{Action, Position},
+    fumen::{self, FumenError},
+    game::{self, Game, GameSummary, Stats},
+    garbage::Garbage,
+    net::{FeedForwardNetwork, NetError, NodeEval},
+    options::{OptionError, OptionInfo, Options},
+    pos::{
+        Action, GameOver, Position, BOARD_HEIGHT, BOARD_WIDTH, FEATURE_NAMES, MAX_HEIGHT,
+        MAX_WIDTH, PIECE_ARRAY,
+    },
     search::Search,
+    timeline::Timeline,
+    versus::{self, MatchStats, VersusSummary},
 };
 
 // lazy_static! {
 //     pub static ref POSITION_HISTORY: Mutex<Vec<String>> = Mutex::new(Vec::new());
 // }
 
-#[derive(Deserialize)]
+const ENGINE_NAME: &str = "Denis";
+
+/// Bumped whenever a message changes in a way old clients can't ignore.
+const PROTOCOL_VERSION: u32 = 2;
+
+/// Most games a `PlayGame` or `Match` message may ask for.
+const MAX_GAMES: usize = 10_000;
+
+/// Every `type` tag accepted by [`In`].
+const MESSAGE_TYPES: [&str; 12] = [
+    "Hello",
+    "Load",
+    "Pos",
+    "Peek",
+    "PlayGame",
+    "Ready",
+    "Go",
+    "SetOption",
+    "Options",
+    "Seed",
+    "Garbage",
+    "Match",
+];
+
+/// A network as sent by the client.
+#[derive(Debug, Deserialize)]
+struct NetSpec {
+    input_nodes: Vec<i64>,
+    output_nodes: Vec<i64>,
+    node_evals: Vec<NodeEval>,
+}
+
+impl NetSpec {
+    fn into_net(self) -> Result<FeedForwardNetwork, NetError> {
+        FeedForwardNetwork::new(self.input_nodes, self.output_nodes, self.node_evals)
+    }
+}
+
+/// A position as sent by the client, in either notation.
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum PosSpec {
+    Tpn {
+        tpn: String,
+    },
+    /// The first page of a fumen, which may be a whole URL.
+    Fumen {
+        fumen: String,
+    },
+}
+
+#[derive(Debug, Deserialize)]
 #[serde(tag = "type")]
 enum In {
-    Load {
-        input_nodes: Vec<i64>,
-        output_nodes: Vec<i64>,
-        node_evals: Vec<(i64, f64, f64, Vec<(i64, f64)>)>,
+    Hello,
+    Load(NetSpec),
+    Pos(PosSpec),
+    Peek {
+        /// Also answer with the position as a fumen.
+        #[serde(default)]
+        fumen: bool,
     },
-    Pos {
-        tpn: String,
+    PlayGame {
+        seed: Option<u64>,
+        games: Option<usize>,
+        seeds: Option<Vec<u64>>,
+        /// Also answer with every game as a fumen, a page per placement.
+        #[serde(default)]
+        fumen: bool,
     },
-    Peek,
-    PlayGame,
     Ready,
     Go,
+    SetOption {
+        name: String,
+        value: serde_json::Value,
+    },
+    Options,
+    Seed {
+        seed: u64,
+    },
+    /// Garbage sent by an opponent, queued until the next placement.
+    Garbage {
+        lines: usize,
+        hole: usize,
+    },
+    /// Versus matches between two networks, from the current position.
+    Match {
+        nets: [NetSpec; 2],
+        games: Option<usize>,
+        seeds: Option<Vec<u64>>,
+    },
 }
 
-#[derive(Serialize)]
+#[derive(Debug, Serialize)]
 #[serde(tag = "type")]
 enum Out {
-    Move { action_list: Vec<Action> },
-    Pos { tpn: String },
-    GameResult { score: i64 },
+    Id {
+        name: String,
+        version: String,
+        protocol_version: u32,
+        /// Size of the board when the TPN does not give one.
+        board_width: usize,
+        board_height: usize,
+        max_board_width: usize,
+        max_board_height: usize,
+        pieces: Vec<String>,
+        features: Vec<String>,
+        messages: Vec<String>,
+    },
+    Move {
+        action_list: Vec<Action>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        timeline: Option<Timeline>,
+        /// Cells of the piece as `(x, y)`, rows counting down from the top
+        /// of the board: first at its spawn, then after each action.
+        cells: Vec<[(i32, i32); 4]>,
+    },
+    Pos {
+        tpn: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        fumen: Option<String>,
+    },
+    GameResult {
+        score: i64,
+        seed: u64,
+        /// `None` when the game stopped at `max_pieces`.
+        reason: Option<GameOver>,
+        pieces: usize,
+        lines: usize,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        fumen: Option<String>,
+    },
+    GameStats {
+        games: Vec<GameSummary>,
+        /// The games as fumens, in the same order.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        fumens: Option<Vec<String>>,
+        #[serde(flatten)]
+        stats: Stats,
+    },
+    MatchResult {
+        matches: Vec<VersusSummary>,
+        #[serde(flatten)]
+        stats: MatchStats,
+    },
+    Options {
+        options: Vec<OptionInfo>,
+    },
+    Error {
+        code: ErrorCode,
+        message: String,
+        line: String,
+    },
     Ok,
     Ko,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+enum ErrorCode {
+    InvalidJson,
+    UnknownType,
+    InvalidMessage,
+    InvalidTpn,
+    InvalidFumen,
+    InvalidOption,
+    NoNetwork,
+    Network,
+}
+
+#[derive(Debug)]
+struct CommError {
+    code: ErrorCode,
+    message: String,
+}
+
+impl CommError {
+    fn new(code: ErrorCode, message: impl Into<String>) -> Self {
+        CommError {
+            code,
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for CommError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}: {}", self.code, self.message)
+    }
+}
+
+impl From<NetError> for CommError {
+    fn from(err: NetError) -> Self {
+        CommError::new(ErrorCode::Network, err.to_string())
+    }
+}
+
+impl From<FumenError> for CommError {
+    fn from(err: FumenError) -> Self {
+        CommError::new(ErrorCode::InvalidFumen, err.to_string())
+    }
+}
+
+impl From<OptionError> for CommError {
+    fn from(err: OptionError) -> Self {
+        CommError::new(ErrorCode::InvalidOption, err.to_string())
+    }
+}
+
 fn send(msg: &Out) -> io::Result<()> {
     println!("{}", serde_json::to_string(msg)?);
     Ok(())
 }
 
+fn parse(line: &str) -> Result<In, CommError> {
+    let value: serde_json::Value = serde_json::from_str(line)
+        .map_err(|err| CommError::new(ErrorCode::InvalidJson, err.to_string()))?;
+
+    let known_type = value
+        .get("type")
+        .and_then(|ty| ty.as_str())
+        .map(|ty| MESSAGE_TYPES.contains(&ty));
+
+    serde_json::from_value(value).map_err(|err| match known_type {
+        Some(true) => CommError::new(ErrorCode::InvalidMessage, err.to_string()),
+        _ => CommError::new(ErrorCode::UnknownType, err.to_string()),
+    })
+}
+
+struct Session {
+    game: Game,
+    net: Option<FeedForwardNetwork>,
+    search: Search,
+    options: Options,
+}
+
+impl Session {
+    fn new() -> Self {
+        let options = Options::default();
+
+        Session {
+            game: Game::new(Position::default(), Game::random_seed()),
+            net: None,
+            search: Search::new(&options),
+            options,
+        }
+    }
+
+    fn handle(&mut self, msg: In) -> Result<Option<Out>, CommError> {
+        match msg {
+            In::Hello => Ok(Some(Out::Id {
+                name: ENGINE_NAME.to_string(),
+                version: env!("CARGO_PKG_VERSION").to_string(),
+                protocol_version: PROTOCOL_VERSION,
+                board_width: BOARD_WIDTH,
+                board_height: BOARD_HEIGHT,
+                max_board_width: MAX_WIDTH,
+                max_board_height: MAX_HEIGHT,
+                pieces: PIECE_ARRAY.iter().map(|piece| piece.to_string()).collect(),
+                features: FEATURE_NAMES.iter().map(|name| name.to_string()).collect(),
+                messages: MESSAGE_TYPES.iter().map(|ty| ty.to_string()).collect(),
+            })),
+            In::Load(net) => {
+                self.net = Some(net.into_net()?);
+                Ok(None)
+            }
+            In::Pos(PosSpec::Tpn { tpn }) => {
+                self.game.pos = Position::from_str(&tpn)
+                    .map_err(|err| CommError::new(ErrorCode::InvalidTpn, err.to_string()))?;
+                self.game.pos.set_randomizer(self.options.randomizer);
+                Ok(None)
+            }
+            In::Pos(PosSpec::Fumen { fumen }) => {
+                self.game.pos = fumen::decode_position(&fumen)?;
+                self.game.pos.set_randomizer(self.options.randomizer);
+                Ok(None)
+            }
+            In::Seed { seed } => {
+                self.game.reseed(seed);
+                Ok(None)
+            }
+            In::Garbage { lines, hole } => {
+                if hole >= self.game.pos.width() {
+                    return Err(CommError::new(
+                        ErrorCode::InvalidMessage,
+                        format!("Garbage hole {} is off the board", hole),
+                    ));
+                }
+                if lines > self.game.pos.height() {
+                    return Err(CommError::new(
+                        ErrorCode::InvalidMessage,
+                        format!("{} garbage lines are more than the board height", lines),
+                    ));
+                }
+
+                self.game.pos.receive_garbage(Garbage { lines, hole });
+                Ok(None)
+            }
+            In::Go => {
+                let nn = self.net.as_mut().ok_or_else(no_network)?;
+                let start = Instant::now();
+
+                match self
+                    .game
+                    .step(&mut self.search, nn, &self.options.rules())?
+                {
+                    Some(best_move) => {
+                        let end = Instant::now();
+                        eprintln!("Thinking time: {}", (end - start).as_millis());
+                        Ok(Some(Out::Move {
+                            action_list: best_move.action_list,
+                            timeline: best_move.timeline,
+                            cells: best_move.cells,
+                        }))
+                    }
+                    None => {
+                        let summary = self.game.summary();
+                        Ok(Some(Out::GameResult {
+                            score: summary.score,
+                            seed: summary.seed,
+                            reason: summary.game_over,
+                            pieces: summary.pieces,
+                            lines: summary.lines,
+                            fumen: None,
+                        }))
+                    }
+                }
+            }
+            In::Peek { fumen } => Ok(Some(Out::Pos {
+                tpn: self.game.pos.to_string(),
+                fumen: match fumen {
+                    true => Some(fumen::encode_position(&self.game.pos)?),
+                    false => None,
+                },
+            })),
+            In::PlayGame {
+                seed,
+                games: None,
+                seeds: None,
+                fumen,
+            } => {
+                let seed = seed.unwrap_or_else(Game::random_seed);
+                let (games, fumens) = self.play_games(&[seed], fumen)?;
+                let summary = games[0];
+
+                Ok(Some(Out::GameResult {
+                    score: summary.score,
+                    seed: summary.seed,
+                    reason: summary.game_over,
+                    pieces: summary.pieces,
+                    lines: summary.lines,
+                    fumen: fumens.map(|mut fumens| fumens.swap_remove(0)),
+                }))
+            }
+            In::PlayGame { seed: Some(_), .. } => Err(CommError::new(
+                ErrorCode::InvalidMessage,
+                "PlayGame takes either a seed or games and seeds",
+            )),
+            In::PlayGame {
+                games,
+                seeds,
+                fumen,
+                ..
+            } => {
+                let seeds = game_seeds("PlayGame", games, seeds)?;
+                let (games, fumens) = self.play_games(&seeds, fumen)?;
+                let scores: Vec<i64> = games.iter().map(|game| game.score).collect();
+
+                Ok(Some(Out::GameStats {
+                    stats: Stats::from_scores(&scores).unwrap(),
+                    games,
+                    fumens,
+                }))
+            }
+            In::Match { nets, games, seeds } => {
+                let seeds = game_seeds("Match", games, seeds)?;
+                let [first, second] = nets.map(NetSpec::into_net);
+                let (first, second) = (first?, second?);
+                let pos = take_pos(&mut self.game.pos, &self.options);
+                let matches = versus::play_matches(&pos, &seeds, [&first, &second], &self.options)?;
+
+                Ok(Some(Out::MatchResult {
+                    stats: MatchStats::from_summaries(&matches).unwrap(),
+                    matches,
+                }))
+            }
+            In::Ready => match self.net {
+                Some(_) => Ok(Some(Out::Ok)),
+                None => Ok(Some(Out::Ko)),
+            },
+            In::SetOption { name, value } => {
+                self.options.set(&name, &value)?;
+                self.search = Search::new(&self.options);
+                self.game.pos.set_randomizer(self.options.randomizer);
+                Ok(None)
+            }
+            In::Options => Ok(Some(Out::Options {
+                options: self.options.list(),
+            })),
+        }
+    }
+
+    /// Plays a game per seed from the current position, along with their
+    /// fumens when `fumen` is set.
+    fn play_games(
+        &mut self,
+        seeds: &[u64],
+        fumen: bool,
+    ) -> Result<(Vec<GameSummary>, Option<Vec<String>>), CommError> {
+        let nn = self.net.as_ref().ok_or_else(no_network)?;
+        if fumen {
+            fumen::check_board(self.game.pos.board())?;
+        }
+        let pos = take_pos(&mut self.game.pos, &self.options);
+
+        if !fumen {
+            return Ok((game::play_games(&pos, seeds, nn, &self.options)?, None));
+        }
+
+        let (games, fumens) = game::record_games(&pos, seeds, nn, &self.options)?
+            .into_iter()
+            .unzip();
+        Ok((games, Some(fumens)))
+    }
+}
+
+/// Takes the current position to play games from, leaving a fresh one
+/// dealt by the chosen randomizer.
+fn take_pos(pos: &mut Position, options: &Options) -> Position {
+    let taken = std::mem::take(pos);
+    pos.set_randomizer(options.randomizer);
+    taken
+}
+
+/// Seeds of the games asked by a `message`, the ones not given being
+/// drawn at random.
+fn game_seeds(
+    message: &str,
+    games: Option<usize>,
+    seeds: Option<Vec<u64>>,
+) -> Result<Vec<u64>, CommError> {
+    let mut seeds = seeds.unwrap_or_default();
+    let games = games.unwrap_or(seeds.len());
+
+    if games < seeds.len() {
+        return Err(CommError::new(
+            ErrorCode::InvalidMessage,
+            format!("Got {} seeds for {} games", seeds.len(), games),
+        ));
+    }
+
+    if games == 0 {
+        return Err(CommError::new(
+            ErrorCode::InvalidMessage,
+            format!("{} needs at least one game", message),
+        ));
+    }
+
+    if games > MAX_GAMES {
+        return Err(CommError::new(
+            ErrorCode::InvalidMessage,
+            format!(
+                "{} plays at most {} games, got {}",
+                message, MAX_GAMES, games
+            ),
+        ));
+    }
+
+    seeds.resize_with(games, Game::random_seed);
+    Ok(seeds)
+}
+
+fn no_network() -> CommError {
+    CommError::new(ErrorCode::NoNetwork, "No network loaded")
+}
+
 pub fn start() -> io::Result<()> {
     let mut buffer = String::new();
     let stdin = io::stdin(); // We get `Stdin` here.
-    let mut pos: Position = Position::default();
-    let mut net: Option<FeedForwardNetwork> = None;
-    let mut search = Search::new();
+    let mut session = Session::new();
 
     loop {
         buffer.clear();
@@ -60,69 +508,233 @@ pub fn start() -> io::Result<()> {
             break;
         }
 
-        let msg_in: In = serde_json::from_str(&buffer).expect("");
+        let line = buffer.trim_end();
+        if line.is_empty() {
+            continue;
+        }
 
-        match msg_in {
-            In::Load {
-                input_nodes,
-                output_nodes,
-                node_evals,
-            } => {
-                net = Some(FeedForwardNetwork::new(
-                    input_nodes,
-                    output_nodes,
-                    node_evals,
-                ));
-            }
-            In::Pos { tpn } => {
-                // TODO: Clean error handling
-                pos = Position::from_str(&tpn).unwrap();
-            }
-            In::Go => {
-                if let Some(nn) = &mut net {
-                    let start = Instant::now();
-
-                    match search.run(nn, &pos) {
-                        Some((best, action_list)) => {
-                            pos = pos
-                                .apply_move(pos.current_piece, best.0, best.1, best.2, true);
-                            let end = Instant::now();
-                            eprintln!("Thinking time: {}", (end - start).as_millis());
-                            send(&Out::Move { action_list })?;
-                        },
-                        None => send(&Out::GameResult { score: pos.score })?
-                    }
-                }
-            }
-            In::Peek => {
-                println!(
-                    "{}",
-                    serde_json::to_string(&Out::Pos {
-                        tpn: pos.to_string()
-                    })?
-                )
+        match parse(line).and_then(|msg| session.handle(msg)) {
+            Ok(Some(msg_out)) => send(&msg_out)?,
+            Ok(None) => {}
+            Err(err) => {
+                eprintln!("{}", err);
+                send(&Out::Error {
+                    code: err.code,
+                    message: err.message,
+                    line: line.to_string(),
+                })?
             }
-            In::PlayGame => {
-                if let Some(nn) = &mut net {
-                    for _ in 0..1000 {
-                        match search.run(nn, &pos) {
-                            Some((mv, _)) => {
-                                pos = pos.apply_move(pos.current_piece, mv.0, mv.1, mv.2, true);
-                                // POSITION_HISTORY.lock().unwrap().push(pos.to_string());
-                            },
-                            None => break
-                        }
-                    }
-                    send(&Out::GameResult { score: pos.score })?;
-                    pos = Position::default();
-                };
-            }
-            In::Ready => match net {
-                Some(_) => send(&Out::Ok)?,
-                None => send(&Out::Ko)?,
-            },
         }
     }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{net::tests::sample_parts, randomizer::RandomizerKind};
+
+    fn sample_spec() -> NetSpec {
+        let (input_nodes, output_nodes, node_evals) = sample_parts();
+        NetSpec {
+            input_nodes,
+            output_nodes,
+            node_evals,
+        }
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert_eq!(parse("{").unwrap_err().code, ErrorCode::InvalidJson);
+        assert_eq!(
+            parse(r#"{"type": "Nope"}"#).unwrap_err().code,
+            ErrorCode::UnknownType
+        );
+        assert_eq!(
+            parse(r#"{"type": "Pos"}"#).unwrap_err().code,
+            ErrorCode::InvalidMessage
+        );
+        assert!(parse(r#"{"type": "Peek"}"#).is_ok());
+    }
+
+    #[test]
+    fn test_message_types_parse() {
+        for ty in MESSAGE_TYPES {
+            let err = parse(&format!(r#"{{"type": "{}", "tpn": 0}}"#, ty)).err();
+            assert!(err.is_none_or(|err| err.code == ErrorCode::InvalidMessage));
+        }
+    }
+
+    #[test]
+    fn test_session_keeps_going() {
+        let mut session = Session::new();
+
+        let err = session.handle(In::Go).unwrap_err();
+        assert_eq!(err.code, ErrorCode::NoNetwork);
+
+        let err = session
+            .handle(In::Pos(PosSpec::Tpn {
+                tpn: "garbage".to_string(),
+            }))
+            .unwrap_err();
+        assert_eq!(err.code, ErrorCode::InvalidTpn);
+
+        for (output_nodes, links) in [(vec![], vec![(-1, 1.)]), (vec![0], vec![(1, 1.)])] {
+            let err = session
+                .handle(In::Load(NetSpec {
+                    input_nodes: vec![-1, -2],
+                    output_nodes,
+                    node_evals: vec![(0, 0., 1., links)],
+                }))
+                .unwrap_err();
+            assert_eq!(err.code, ErrorCode::Network);
+        }
+        assert!(matches!(session.handle(In::Ready), Ok(Some(Out::Ko))));
+
+        session
+            .handle(In::Load(NetSpec {
+                input_nodes: vec![-1, -2],
+                output_nodes: vec![0],
+                node_evals: vec![(0, 0., 1., vec![(-1, 1.)])],
+            }))
+            .unwrap();
+        let err = session
+            .handle(In::PlayGame {
+                seed: None,
+                games: None,
+                seeds: None,
+                fumen: false,
+            })
+            .unwrap_err();
+        assert_eq!(err.code, ErrorCode::Network);
+
+        assert!(matches!(session.handle(In::Ready), Ok(Some(Out::Ok))));
+    }
+
+    #[test]
+    fn test_match() {
+        let mut session = Session::new();
+        session.options.max_depth = 2;
+        session.options.max_pieces = 5;
+        session
+            .handle(In::SetOption {
+                name: "randomizer".to_string(),
+                value: "bag".into(),
+            })
+            .unwrap();
+
+        let (input_nodes, output_nodes, node_evals) = sample_parts();
+        let net = serde_json::json!({
+            "input_nodes": input_nodes,
+            "output_nodes": output_nodes,
+            "node_evals": node_evals,
+        });
+        let msg = parse(&format!(
+            r#"{{"type": "Match", "nets": [{}, {}], "games": 2, "seeds": [5]}}"#,
+            net, net
+        ))
+        .unwrap();
+
+        let Ok(Some(Out::MatchResult { matches, stats })) = session.handle(msg) else {
+            panic!("Match should answer with its results");
+        };
+        assert_eq!(matches.len(), 2);
+        assert_eq!(matches[0].seed, 5);
+        assert_eq!(stats.draws, 2);
+        assert_eq!(stats.sides[1].survival.mean, 5.);
+        assert_eq!(session.game.pos.randomizer.kind(), RandomizerKind::Bag);
+
+        let msg = parse(&format!(r#"{{"type": "Match", "nets": [{}]}}"#, net));
+        assert_eq!(msg.unwrap_err().code, ErrorCode::InvalidMessage);
+    }
+
+    #[test]
+    fn test_game_counts() {
+        let mut session = Session::new();
+
+        for msg in [
+            r#"{"type": "PlayGame", "seed": 1, "games": 2}"#,
+            r#"{"type": "PlayGame", "seed": 1, "seeds": [2]}"#,
+            r#"{"type": "PlayGame", "games": 10001}"#,
+            r#"{"type": "PlayGame", "seeds": []}"#,
+        ] {
+            let err = session.handle(parse(msg).unwrap()).unwrap_err();
+            assert_eq!(err.code, ErrorCode::InvalidMessage, "{}", msg);
+        }
+
+        let err = session
+            .handle(In::PlayGame {
+                seed: None,
+                games: Some(MAX_GAMES),
+                seeds: None,
+                fumen: false,
+            })
+            .unwrap_err();
+        assert_eq!(err.code, ErrorCode::NoNetwork);
+    }
+
+    #[test]
+    fn test_garbage_message() {
+        let mut session = Session::new();
+
+        let err = session
+            .handle(In::Garbage { lines: 2, hole: 10 })
+            .unwrap_err();
+        assert_eq!(err.code, ErrorCode::InvalidMessage);
+        let err = session
+            .handle(In::Garbage {
+                lines: usize::MAX,
+                hole: 3,
+            })
+            .unwrap_err();
+        assert_eq!(err.code, ErrorCode::InvalidMessage);
+
+        session.handle(In::Garbage { lines: 2, hole: 3 }).unwrap();
+        let Ok(Some(Out::Pos { tpn, .. })) = session.handle(In::Peek { fumen: false }) else {
+            panic!("Peek should answer with the position");
+        };
+        assert!(tpn.contains(" 2@3 0 "));
+    }
+
+    #[test]
+    fn test_fumen_messages() {
+        let mut session = Session::new();
+        session.options.max_depth = 2;
+        session.options.max_pieces = 4;
+
+        let msg = parse(r#"{"type": "Pos", "fumen": "v115@9gF8DeF8DeF8DeF8NeAgH"}"#).unwrap();
+        let err = session.handle(msg).unwrap_err();
+        assert_eq!(err.code, ErrorCode::InvalidFumen);
+
+        let tpn = format!("{}GGGGGG4/ T SZ 0", "/".repeat(21));
+        session
+            .handle(In::Pos(PosSpec::Tpn { tpn: tpn.clone() }))
+            .unwrap();
+        let Ok(Some(Out::Pos {
+            fumen: Some(fumen), ..
+        })) = session.handle(parse(r#"{"type": "Peek", "fumen": true}"#).unwrap())
+        else {
+            panic!("Peek should answer with a fumen when asked");
+        };
+
+        session.handle(In::Peek { fumen: false }).unwrap();
+        let msg = parse(&format!(r#"{{"type": "Pos", "fumen": "{}"}}"#, fumen)).unwrap();
+        session.handle(msg).unwrap();
+        assert_eq!(
+            session.game.pos.board(),
+            Position::from_str(&tpn).unwrap().board()
+        );
+
+        session.handle(In::Load(sample_spec())).unwrap();
+        let msg = parse(r#"{"type": "PlayGame", "seeds": [3], "fumen": true}"#).unwrap();
+        let Ok(Some(Out::GameStats {
+            fumens: Some(fumens),
+            ..
+        })) = session.handle(msg)
+        else {
+            panic!("PlayGame should answer with the fumens when asked");
+        };
+        assert_eq!(fumen::decode(&fumens[0]).unwrap().len(), 5);
+    }
+}
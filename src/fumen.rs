@@ -0,0 +1,842 @@
+//! Fumen, the diagram format most Tetris tools share positions in, as its
+//! `v115@` strings.
+//!
+//! A fumen is a list of pages, each a 10 wide field of 23 rows plus a
+//! garbage row under the floor, an optional piece and a comment. Fields
+//! are stored as run lengths of the change from the previous page, after
+//! its piece locked and its full rows cleared.
+
+use std::fmt;
+
+use crate::{
+    piece::PieceKind,
+    pos::{hash_board, BitBoard, Board, Color, Placement, Position, Queue, MAX_PREVIEW},
+};
+
+const VERSION_PREFIX: &str = "v115@";
+const ENCODE_TABLE: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+pub const FIELD_WIDTH: usize = 10;
+/// Rows of the field, not counting the garbage row.
+pub const FIELD_TOP: usize = 23;
+const FIELD_HEIGHT: usize = FIELD_TOP + 1;
+const FIELD_BLOCKS: usize = FIELD_WIDTH * FIELD_HEIGHT;
+
+/// Comments are stored as printable ASCII, 4 characters to 5 values.
+const COMMENT_BASE: usize = 96;
+const MAX_COMMENT: usize = 4095;
+
+/// Prefix of the comments giving hold, current piece and queue, as in
+/// `#Q=[I](T)SZLO`.
+const QUIZ_PREFIX: &str = "#Q=";
+
+/// Cells of a fumen page, row 0 at the top and the last row being the
+/// garbage row under the floor.
+pub type Field = [[Color; FIELD_WIDTH]; FIELD_HEIGHT];
+
+const EMPTY_FIELD: Field = [[Color::Empty; FIELD_WIDTH]; FIELD_HEIGHT];
+
+/// Cells of every piece in its SRS spawn orientation, relative to its
+/// rotation centre and `y` going up, in [`PieceKind::ALL`] order.
+const PIECE_CELLS: [[(i32, i32); 4]; 7] = [
+    [(0, 0), (-1, 0), (1, 0), (2, 0)],
+    [(0, 0), (0, 1), (1, 0), (1, 1)],
+    [(0, 0), (-1, 0), (1, 0), (-1, 1)],
+    [(0, 0), (-1, 0), (1, 0), (1, 1)],
+    [(0, 0), (-1, 0), (0, 1), (1, 1)],
+    [(0, 0), (-1, 0), (1, 0), (0, 1)],
+    [(0, 0), (1, 0), (0, 1), (-1, 1)],
+];
+
+/// Fumen code of each orientation, by clockwise quarter turns from spawn.
+/// The table is its own inverse.
+const ROTATION_CODES: [usize; 4] = [2, 1, 0, 3];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FumenError {
+    /// No `v115@` data in the string.
+    NotFumen,
+    /// A character outside of the fumen alphabet.
+    BadChar {
+        offset: usize,
+    },
+    /// The data ends in the middle of a page.
+    Truncated,
+    /// A field runs past its last cell or holds an unknown block.
+    BadField,
+    /// A piece of an unknown kind or off the field.
+    BadPiece,
+    BadComment,
+    /// Fumen fields are 10 wide and at most 23 tall.
+    BoardSize {
+        width: usize,
+        height: usize,
+    },
+    /// The page neither has a piece nor names one in a quiz comment.
+    NoCurrentPiece,
+}
+
+impl fmt::Display for FumenError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FumenError::NotFumen => write!(f, "No {} data", VERSION_PREFIX),
+            FumenError::BadChar { offset } => write!(f, "Bad character at byte {}", offset),
+            FumenError::Truncated => write!(f, "Data ends in the middle of a page"),
+            FumenError::BadField => write!(f, "Bad field"),
+            FumenError::BadPiece => write!(f, "Bad piece"),
+            FumenError::BadComment => write!(f, "Bad comment"),
+            FumenError::BoardSize { width, height } => write!(
+                f,
+                "A {}x{} board doesn't fit a {}x{} fumen field",
+                width, height, FIELD_WIDTH, FIELD_TOP
+            ),
+            FumenError::NoCurrentPiece => write!(f, "No current piece"),
+        }
+    }
+}
+
+/// A piece as fumen places it, by its SRS orientation and rotation centre.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FumenPiece {
+    pub kind: PieceKind,
+    /// Clockwise quarter turns from the spawn orientation.
+    pub rotation: usize,
+    /// Column and row of the centre, rows counting up from the bottom of
+    /// the field and -1 being the garbage row.
+    pub x: i32,
+    pub y: i32,
+}
+
+impl FumenPiece {
+    /// Cells of the piece, with `y` counting up like the centre.
+    pub fn cells(&self) -> [(i32, i32); 4] {
+        PIECE_CELLS[self.kind.index()].map(|(dx, dy)| {
+            let (dx, dy) = match self.rotation {
+                0 => (dx, dy),
+                1 => (dy, -dx),
+                2 => (-dx, -dy),
+                _ => (-dy, dx),
+            };
+            (self.x + dx, self.y + dy)
+        })
+    }
+
+    /// The piece covering `cells`, in the first orientation that does.
+    fn covering(kind: PieceKind, mut cells: [(i32, i32); 4]) -> Option<FumenPiece> {
+        cells.sort_unstable();
+
+        (0..4).find_map(|rotation| {
+            let at_origin = FumenPiece {
+                kind,
+                rotation,
+                x: 0,
+                y: 0,
+            };
+            let mut offsets = at_origin.cells();
+            offsets.sort_unstable();
+
+            let (x, y) = (cells[0].0 - offsets[0].0, cells[0].1 - offsets[0].1);
+            let piece = FumenPiece { x, y, ..at_origin };
+            let mut placed = piece.cells();
+            placed.sort_unstable();
+
+            (placed == cells).then_some(piece)
+        })
+    }
+
+    /// Fumen stores some orientations of I, O, S and Z by another cell
+    /// than their rotation centre, this far from it.
+    fn stored_offset(&self) -> (i32, i32) {
+        match (self.kind, self.rotation) {
+            (PieceKind::O, 0) => (0, 1),
+            (PieceKind::O, 2) => (-1, 0),
+            (PieceKind::O, 3) => (-1, 1),
+            (PieceKind::I, 2) => (-1, 0),
+            (PieceKind::I, 3) => (0, 1),
+            (PieceKind::S, 0) => (0, 1),
+            (PieceKind::S, 1) => (1, 0),
+            (PieceKind::Z, 0) => (0, 1),
+            (PieceKind::Z, 3) => (-1, 0),
+            _ => (0, 0),
+        }
+    }
+}
+
+/// One page of a fumen.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Page {
+    pub field: Field,
+    pub piece: Option<FumenPiece>,
+    pub comment: Option<String>,
+}
+
+impl Page {
+    /// The board of `pos`, with the piece of `placement` about to lock.
+    /// Panics if the board doesn't fit a fumen field.
+    pub fn of(pos: &Position, placement: Option<Placement>) -> Page {
+        let board = pos.board();
+        assert!(check_board(board).is_ok());
+
+        let top = FIELD_TOP - board.height();
+        let mut field = EMPTY_FIELD;
+        for (y, row) in board.rows().enumerate() {
+            field[top + y].copy_from_slice(row);
+        }
+
+        let piece = placement.map(|placement| {
+            let shape = placement.piece.shape(placement.rot as i32);
+            let cells = shape.cells().map(|(dx, dy)| {
+                let y = board.height() - 1 - (placement.y + dy);
+                ((placement.x + dx) as i32, y as i32)
+            });
+
+            FumenPiece::covering(placement.piece, cells).unwrap()
+        });
+
+        Page {
+            field,
+            piece,
+            comment: None,
+        }
+    }
+
+    /// The field once the piece locked and the full rows cleared, which the
+    /// next page is stored against.
+    fn locked(&self) -> Field {
+        let mut field = self.field;
+
+        if let Some(piece) = self.piece {
+            for (x, y) in piece.cells() {
+                field[(FIELD_TOP as i32 - 1 - y) as usize][x as usize] = piece.kind.color();
+            }
+        }
+
+        let mut to = FIELD_TOP;
+        for from in (0..FIELD_TOP).rev() {
+            if field[from].contains(&Color::Empty) {
+                to -= 1;
+                field[to] = field[from];
+            }
+        }
+        field[..to].fill([Color::Empty; FIELD_WIDTH]);
+
+        field
+    }
+}
+
+/// Whether `board` fits a fumen field.
+pub fn check_board(board: &Board<Color>) -> Result<(), FumenError> {
+    if board.width() != FIELD_WIDTH || board.height() > FIELD_TOP {
+        return Err(FumenError::BoardSize {
+            width: board.width(),
+            height: board.height(),
+        });
+    }
+
+    Ok(())
+}
+
+fn block(color: Color) -> usize {
+    match color {
+        Color::Empty | Color::Random => 0,
+        Color::I => 1,
+        Color::L => 2,
+        Color::O => 3,
+        Color::Z => 4,
+        Color::T => 5,
+        Color::J => 6,
+        Color::S => 7,
+        Color::Garbage => 8,
+    }
+}
+
+fn block_color(block: usize) -> Option<Color> {
+    const COLORS: [Color; 9] = [
+        Color::Empty,
+        Color::I,
+        Color::L,
+        Color::O,
+        Color::Z,
+        Color::T,
+        Color::J,
+        Color::S,
+        Color::Garbage,
+    ];
+
+    COLORS.get(block).copied()
+}
+
+/// Little endian base 64 numbers, each taking a fixed number of digits.
+#[derive(Default)]
+struct Values(Vec<u8>);
+
+impl Values {
+    fn push(&mut self, mut value: usize, digits: usize) {
+        for _ in 0..digits {
+            self.0.push((value % 64) as u8);
+            value /= 64;
+        }
+    }
+}
+
+struct Reader {
+    values: Vec<u8>,
+    next: usize,
+}
+
+impl Reader {
+    fn poll(&mut self, digits: usize) -> Result<usize, FumenError> {
+        let values = self
+            .values
+            .get(self.next..self.next + digits)
+            .ok_or(FumenError::Truncated)?;
+        self.next += digits;
+
+        Ok(values
+            .iter()
+            .rev()
+            .fold(0, |value, &v| value * 64 + v as usize))
+    }
+
+    fn is_done(&self) -> bool {
+        self.next >= self.values.len()
+    }
+}
+
+/// Runs of equal changes from `prev` to `field`, as stored.
+fn field_runs(prev: &Field, field: &Field) -> Vec<usize> {
+    let mut runs = Vec::new();
+    let mut run: Option<(usize, usize)> = None;
+
+    for (prev_row, row) in prev.iter().zip(field) {
+        for (&prev_cell, &cell) in prev_row.iter().zip(row) {
+            let diff = block(cell) + 8 - block(prev_cell);
+            run = match run {
+                Some((run_diff, count)) if run_diff == diff => Some((diff, count + 1)),
+                Some((run_diff, count)) => {
+                    runs.push(run_diff * FIELD_BLOCKS + count - 1);
+                    Some((diff, 1))
+                }
+                None => Some((diff, 1)),
+            };
+        }
+    }
+
+    let (diff, count) = run.unwrap();
+    runs.push(diff * FIELD_BLOCKS + count - 1);
+    runs
+}
+
+/// The characters JavaScript's `escape` leaves alone.
+fn is_unescaped(c: char) -> bool {
+    c.is_ascii_alphanumeric() || "@*_+-./".contains(c)
+}
+
+fn escape(s: &str) -> String {
+    let mut escaped = String::new();
+    for unit in s.encode_utf16() {
+        match char::from_u32(unit as u32) {
+            Some(c) if is_unescaped(c) => escaped.push(c),
+            _ if unit < 256 => escaped += &format!("%{:02X}", unit),
+            _ => escaped += &format!("%u{:04X}", unit),
+        }
+    }
+
+    escaped
+}
+
+fn unescape(s: &str) -> String {
+    let mut units = Vec::new();
+    let mut rest = s;
+    while let Some(c) = rest.chars().next() {
+        let hex = |digits: &str| {
+            digits
+                .bytes()
+                .all(|b| b.is_ascii_hexdigit())
+                .then(|| u16::from_str_radix(digits, 16).unwrap())
+        };
+        let (unit, len) = match (rest.get(1..2), rest.get(2..6), rest.get(1..3)) {
+            (Some("u"), Some(digits), _) if c == '%' && hex(digits).is_some() => {
+                (hex(digits).unwrap(), 6)
+            }
+            (_, _, Some(digits)) if c == '%' && hex(digits).is_some() => (hex(digits).unwrap(), 3),
+            _ => (c as u16, c.len_utf8()),
+        };
+        units.push(unit);
+        rest = &rest[len..];
+    }
+
+    String::from_utf16_lossy(&units)
+}
+
+/// `pages` as a `v115@` string.
+pub fn encode(pages: &[Page]) -> String {
+    let mut values = Values::default();
+    let mut prev = EMPTY_FIELD;
+    let mut prev_comment = "";
+    // Where the count of pages repeating the last field is, if they do
+    let mut repeat: Option<usize> = None;
+
+    for (i, page) in pages.iter().enumerate() {
+        let runs = field_runs(&prev, &page.field);
+        let unchanged = runs == [8 * FIELD_BLOCKS + FIELD_BLOCKS - 1];
+
+        match repeat {
+            Some(at) if unchanged && values.0[at] < 63 => values.0[at] += 1,
+            _ => {
+                for &run in &runs {
+                    values.push(run, 2);
+                }
+                repeat = None;
+                if unchanged {
+                    values.push(0, 1);
+                    repeat = Some(values.0.len() - 1);
+                }
+            }
+        }
+
+        let comment = page.comment.as_deref().unwrap_or("");
+        let comment_changed = comment != prev_comment;
+
+        let (kind, rotation, position) = match page.piece {
+            Some(piece) => {
+                let (dx, dy) = piece.stored_offset();
+                let (x, y) = (piece.x + dx, piece.y + dy);
+                (
+                    block(piece.kind.color()),
+                    ROTATION_CODES[piece.rotation],
+                    (FIELD_TOP as i32 - y - 1) as usize * FIELD_WIDTH + x as usize,
+                )
+            }
+            None => (0, 0, 0),
+        };
+        // Not locking, comment, colors (first page only), mirror, rise
+        let flags = [false, comment_changed, i == 0, false, false];
+        let flags = flags
+            .iter()
+            .fold(0, |value, &flag| value * 2 + flag as usize);
+        values.push(
+            ((flags * FIELD_BLOCKS + position) * 4 + rotation) * 8 + kind,
+            3,
+        );
+
+        if comment_changed {
+            let mut escaped = escape(comment);
+            escaped.truncate(MAX_COMMENT);
+            values.push(escaped.len(), 2);
+
+            for chunk in escaped.as_bytes().chunks(4) {
+                let value = chunk
+                    .iter()
+                    .rev()
+                    .fold(0, |value, &c| value * COMMENT_BASE + (c - b' ') as usize);
+                values.push(value, 5);
+            }
+        }
+
+        prev_comment = comment;
+        prev = page.locked();
+    }
+
+    // Viewers expect a `?` after the first 42 characters, then every 47
+    let mut data = String::from(VERSION_PREFIX);
+    for (i, &value) in values.0.iter().enumerate() {
+        if i >= 42 && (i - 42) % 47 == 0 {
+            data.push('?');
+        }
+        data.push(ENCODE_TABLE[value as usize] as char);
+    }
+
+    data
+}
+
+/// The pages of the `v115@` data in `s`, which may be a whole URL.
+pub fn decode(s: &str) -> Result<Vec<Page>, FumenError> {
+    let start = s.find(VERSION_PREFIX).ok_or(FumenError::NotFumen)? + VERSION_PREFIX.len();
+    let data = s[start..].split_whitespace().next().unwrap_or("");
+
+    let mut values = Vec::new();
+    for (i, c) in data.bytes().enumerate() {
+        if c == b'?' {
+            continue;
+        }
+        let value = ENCODE_TABLE
+            .iter()
+            .position(|&e| e == c)
+            .ok_or(FumenError::BadChar { offset: start + i })?;
+        values.push(value as u8);
+    }
+
+    let mut reader = Reader { values, next: 0 };
+    let mut pages = Vec::new();
+    let mut prev = EMPTY_FIELD;
+    let mut comment: Option<String> = None;
+    let mut repeat = 0;
+
+    while !reader.is_done() {
+        let mut field = prev;
+
+        if repeat > 0 {
+            repeat -= 1;
+        } else {
+            let mut index = 0;
+            while index < FIELD_BLOCKS {
+                let run = reader.poll(2)?;
+                let (diff, count) = (run / FIELD_BLOCKS, run % FIELD_BLOCKS + 1);
+                if diff > 16 || index + count > FIELD_BLOCKS {
+                    return Err(FumenError::BadField);
+                }
+                if diff == 8 && count == FIELD_BLOCKS {
+                    repeat = reader.poll(1)?;
+                }
+
+                for i in index..index + count {
+                    let cell = &mut field[i / FIELD_WIDTH][i % FIELD_WIDTH];
+                    *cell = (block(*cell) + diff)
+                        .checked_sub(8)
+                        .and_then(block_color)
+                        .ok_or(FumenError::BadField)?;
+                }
+                index += count;
+            }
+        }
+
+        let mut action = reader.poll(3)?;
+        let mut take = |n: usize| {
+            let value = action % n;
+            action /= n;
+            value
+        };
+        let (kind, rotation, position) = (take(8), take(4), take(FIELD_BLOCKS));
+        let [rise, mirror, _colors, comment_changed, not_locking] = [(); 5].map(|_| take(2) == 1);
+
+        let piece = match kind {
+            0 => None,
+            _ => {
+                let kind = block_color(kind)
+                    .and_then(Color::piece)
+                    .ok_or(FumenError::BadPiece)?;
+                let mut piece = FumenPiece {
+                    kind,
+                    rotation: ROTATION_CODES[rotation],
+                    x: (position % FIELD_WIDTH) as i32,
+                    // The garbage row is -1
+                    y: FIELD_TOP as i32 - 1 - (position / FIELD_WIDTH) as i32,
+                };
+                let (dx, dy) = piece.stored_offset();
+                piece.x -= dx;
+                piece.y -= dy;
+
+                let on_field = |(x, y): (i32, i32)| {
+                    (0..FIELD_WIDTH as i32).contains(&x) && (-1..FIELD_TOP as i32).contains(&y)
+                };
+                if !piece.cells().into_iter().all(on_field) {
+                    return Err(FumenError::BadPiece);
+                }
+                Some(piece)
+            }
+        };
+
+        if comment_changed {
+            let len = reader.poll(2)?;
+            let mut escaped = String::new();
+            for _ in 0..len.div_ceil(4) {
+                let mut value = reader.poll(5)?;
+                for _ in 0..4 {
+                    let c = (value % COMMENT_BASE) as u8 + b' ';
+                    if c > b'~' {
+                        return Err(FumenError::BadComment);
+                    }
+                    escaped.push(c as char);
+                    value /= COMMENT_BASE;
+                }
+            }
+            escaped.truncate(len);
+
+            comment = Some(unescape(&escaped)).filter(|comment| !comment.is_empty());
+        }
+
+        let page = Page {
+            field,
+            piece,
+            comment: comment.clone(),
+        };
+
+        prev = if not_locking {
+            page.field
+        } else {
+            page.locked()
+        };
+        if !not_locking && rise {
+            prev.copy_within(1..FIELD_HEIGHT, 0);
+            prev[FIELD_TOP] = [Color::Empty; FIELD_WIDTH];
+        }
+        if !not_locking && mirror {
+            for row in prev[..FIELD_TOP].iter_mut() {
+                row.reverse();
+            }
+        }
+
+        pages.push(page);
+    }
+
+    Ok(pages)
+}
+
+/// `pos` as a single page fumen, its pieces given in a quiz comment.
+pub fn encode_position(pos: &Position) -> Result<String, FumenError> {
+    check_board(pos.board())?;
+
+    let name = |color: Color| match color.piece() {
+        Some(piece) => piece.to_string(),
+        None => String::new(),
+    };
+    let queue: String = pos
+        .queue
+        .iter()
+        .map_while(|&piece| piece.piece())
+        .map(|piece| piece.to_string())
+        .collect();
+    let comment = format!(
+        "{}[{}]({}){}",
+        QUIZ_PREFIX,
+        name(pos.hold_piece),
+        name(pos.current_piece),
+        queue
+    );
+
+    Ok(encode(&[Page {
+        comment: Some(comment),
+        ..Page::of(pos, None)
+    }]))
+}
+
+/// The position on the first page of a fumen. Hold, current piece and
+/// queue come from a quiz comment, the current piece otherwise being the
+/// piece of the page. Queues longer than the preview are cut short.
+pub fn decode_position(s: &str) -> Result<Position, FumenError> {
+    let page = decode(s)?.into_iter().next().ok_or(FumenError::Truncated)?;
+
+    let mut hold_piece = Color::Empty;
+    let mut current_piece = page.piece.map(|piece| piece.kind.color());
+    let mut queue = Queue::new();
+    if let Some(quiz) = page
+        .comment
+        .as_deref()
+        .and_then(|comment| comment.strip_prefix(QUIZ_PREFIX))
+    {
+        let pieces = |s: &str| -> Result<Vec<Color>, FumenError> {
+            s.chars()
+                .map(|c| match Color::try_from(c).map(Color::piece) {
+                    Ok(Some(piece)) => Ok(piece.color()),
+                    _ => Err(FumenError::BadComment),
+                })
+                .collect()
+        };
+
+        let (hold, rest) = quiz
+            .strip_prefix('[')
+            .and_then(|quiz| quiz.split_once(']'))
+            .ok_or(FumenError::BadComment)?;
+        let (current, next) = rest
+            .strip_prefix('(')
+            .and_then(|rest| rest.split_once(')'))
+            .ok_or(FumenError::BadComment)?;
+
+        if let [hold] = pieces(hold)?[..] {
+            hold_piece = hold;
+        }
+        if let [current] = pieces(current)?[..] {
+            current_piece = Some(current);
+        }
+        queue = pieces(next)?.into_iter().take(MAX_PREVIEW).collect();
+    }
+    let current_piece = current_piece.ok_or(FumenError::NoCurrentPiece)?;
+
+    // The top row only joins the board when something is in it
+    let height = match page.field[0].iter().all(|&cell| cell == Color::Empty) {
+        true => FIELD_TOP - 1,
+        false => FIELD_TOP,
+    };
+    let mut board = Board::new(FIELD_WIDTH, height, Color::Empty);
+    for y in 0..height {
+        board[y].copy_from_slice(&page.field[FIELD_TOP - height + y]);
+    }
+
+    let hash = hash_board(&BitBoard::new(&board));
+    let mut pos = Position::new(current_piece, queue, 0, board, hash);
+    pos.hold_piece = hold_piece;
+
+    Ok(pos)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pos::Rules;
+    use rand::{rngs::SmallRng, Rng, SeedableRng};
+    use std::str::FromStr;
+
+    #[test]
+    fn test_known_fumens() {
+        let empty = Page {
+            field: EMPTY_FIELD,
+            piece: None,
+            comment: None,
+        };
+        assert_eq!(encode(std::slice::from_ref(&empty)), "v115@vhAAgH");
+        assert_eq!(decode("v115@vhAAgH"), Ok(vec![empty]));
+
+        // Four rows of garbage with a well on the right, from a URL
+        let fumen = "v115@9gF8DeF8DeF8DeF8NeAgH";
+        let pages = decode(&format!("https://fumen.zui.jp/?{}", fumen)).unwrap();
+        assert_eq!(pages.len(), 1);
+        assert_eq!(
+            pages[0].field[19][..7],
+            [
+                Color::Garbage,
+                Color::Garbage,
+                Color::Garbage,
+                Color::Garbage,
+                Color::Garbage,
+                Color::Garbage,
+                Color::Empty
+            ]
+        );
+        assert_eq!(pages[0].field[22], pages[0].field[19]);
+        assert_eq!(pages[0].field[18], [Color::Empty; FIELD_WIDTH]);
+        assert_eq!(encode(&pages), fumen);
+    }
+
+    #[test]
+    fn test_game_round_trip() {
+        let rules = Rules::default();
+        let mut rng = SmallRng::seed_from_u64(25);
+        let mut pos = Position::default();
+        let mut pages = Vec::new();
+
+        for _ in 0..60 {
+            if pos.is_game_over(&rules).is_some() {
+                break;
+            }
+            let moves = pos.legal_moves(&rules).swap_remove(0);
+            let placement = moves[rng.gen_range(0..moves.len())];
+            let page = Page::of(&pos, Some(placement));
+            pos = pos.apply_move(&rules, placement, Some(&mut rng));
+
+            // Fumen locks the piece into the same cells as the game
+            assert_eq!(page.locked(), Page::of(&pos, None).field);
+            pages.push(page);
+        }
+        pages.push(Page::of(&pos, None));
+        pages[3].comment = Some("Tetris ✓ 100%".to_string());
+
+        let fumen = encode(&pages);
+        assert!(fumen.len() < pages.len() * 12, "{}", fumen);
+        assert_eq!(decode(&fumen), Ok(pages));
+    }
+
+    #[test]
+    fn test_pieces() {
+        for kind in PieceKind::ALL {
+            for rotation in 0..4 {
+                let piece = FumenPiece {
+                    kind,
+                    rotation,
+                    x: 4,
+                    y: 10,
+                };
+                let page = Page {
+                    field: EMPTY_FIELD,
+                    piece: Some(piece),
+                    comment: None,
+                };
+
+                let decoded = decode(&encode(&[page])).unwrap().swap_remove(0);
+                let mut cells = decoded.piece.unwrap().cells();
+                let mut expected = piece.cells();
+                cells.sort_unstable();
+                expected.sort_unstable();
+                assert_eq!(cells, expected);
+            }
+        }
+
+        // Written out by hand from the format: these orientations are
+        // stored at the centre of their twin covering the same cells, and
+        // every O by its top left cell
+        let pages = decode("v115@vhITJJjqBbsBBcB5KB3GBv+A0oA8lA").unwrap();
+        let expected = [
+            (PieceKind::O, 0, [(0, 0), (0, 1), (1, 0), (1, 1)]),
+            (PieceKind::O, 2, [(3, 0), (3, 1), (4, 0), (4, 1)]),
+            (PieceKind::O, 3, [(6, 0), (6, 1), (7, 0), (7, 1)]),
+            (PieceKind::I, 2, [(3, 4), (4, 4), (5, 4), (6, 4)]),
+            (PieceKind::I, 3, [(9, 6), (9, 7), (9, 8), (9, 9)]),
+            (PieceKind::S, 0, [(0, 7), (1, 7), (1, 8), (2, 8)]),
+            (PieceKind::S, 1, [(4, 10), (4, 11), (5, 9), (5, 10)]),
+            (PieceKind::Z, 0, [(0, 14), (1, 13), (1, 14), (2, 13)]),
+            (PieceKind::Z, 3, [(5, 14), (5, 15), (6, 15), (6, 16)]),
+        ];
+        assert_eq!(pages.len(), expected.len());
+        for (page, (kind, rotation, cells)) in pages.iter().zip(expected) {
+            let piece = page.piece.unwrap();
+            let mut decoded = piece.cells();
+            decoded.sort_unstable();
+            assert_eq!(
+                (piece.kind, piece.rotation, decoded),
+                (kind, rotation, cells)
+            );
+        }
+        assert_eq!(encode(&pages), "v115@vhITJJjqBbsBBcB5KB3GBv+A0oA8lA");
+
+        // Right of spawn, the I stands in the column right of its centre
+        let i = FumenPiece::covering(PieceKind::I, [(7, 0), (7, 1), (7, 2), (7, 3)]).unwrap();
+        assert_eq!((i.rotation, i.x, i.y), (1, 7, 2));
+    }
+
+    #[test]
+    fn test_position() {
+        let pos = Position::from_str(&format!("{}GGGGGGGGG1/ T SZL 0 I", "/".repeat(21))).unwrap();
+        let fumen = encode_position(&pos).unwrap();
+        assert_eq!(
+            decode(&fumen).unwrap()[0].comment.as_deref(),
+            Some("#Q=[I](T)SZL")
+        );
+
+        let decoded = decode_position(&fumen).unwrap();
+        assert_eq!(decoded.board(), pos.board());
+        assert_eq!(decoded.hash, pos.hash);
+        assert_eq!(decoded.current_piece, Color::T);
+        assert_eq!(decoded.queue, pos.queue);
+        assert_eq!(decoded.hold_piece, Color::I);
+
+        assert_eq!(
+            decode_position("v115@vhAAgH").err(),
+            Some(FumenError::NoCurrentPiece)
+        );
+        let wide = Position::from_str("12x22:////////////////////// I J 0").unwrap();
+        assert_eq!(
+            encode_position(&wide),
+            Err(FumenError::BoardSize {
+                width: 12,
+                height: 22
+            })
+        );
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(decode("vhAAgH"), Err(FumenError::NotFumen));
+        assert_eq!(
+            decode("v115@vh!AgH"),
+            Err(FumenError::BadChar { offset: 7 })
+        );
+        assert_eq!(decode("v115@vhAAg"), Err(FumenError::Truncated));
+        assert_eq!(decode("v115@/hAAgH"), Err(FumenError::BadField));
+        assert_eq!(decode_position("v115@").err(), Some(FumenError::Truncated));
+        // A T on the garbage row fits, but not pointing down from it
+        assert!(decode("v115@vhA11B").is_ok());
+        assert_eq!(decode("v115@vhAl1B"), Err(FumenError::BadPiece));
+        assert_eq!(unescape(&escape("é 😀 #Q=[](T)")), "é 😀 #Q=[](T)");
+        assert_eq!(escape("a b%"), "a%20b%25");
+    }
+}
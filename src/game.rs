@@ -0,0 +1,407 @@
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+    thread,
+};
+
+use rand::{rngs::SmallRng, Rng, SeedableRng};
+use serde::Serialize;
+
+use crate::{
+    fumen::{self, Page},
+    net::{FeedForwardNetwork, NetError},
+    options::Options,
+    pos::{GameOver, Placement, Position, Rules},
+    search::{BestMove, Search},
+};
+
+/// A position together with the random source that deals its pieces, so
+/// that a game is fully determined by its starting position and seed.
+pub struct Game {
+    pub pos: Position,
+    pub seed: u64,
+    pub pieces: usize,
+    /// Why the game ended, once it has.
+    pub game_over: Option<GameOver>,
+    rng: SmallRng,
+    /// Fumen pages of the placements so far, once recording.
+    pages: Option<Vec<Page>>,
+}
+
+impl Game {
+    pub fn new(pos: Position, seed: u64) -> Self {
+        Game {
+            pos,
+            seed,
+            pieces: 0,
+            game_over: None,
+            rng: SmallRng::seed_from_u64(seed),
+            pages: None,
+        }
+    }
+
+    pub fn random_seed() -> u64 {
+        rand::thread_rng().gen()
+    }
+
+    pub fn set_preview(&mut self, len: usize) {
+        self.pos.set_preview(len, &mut self.rng);
+    }
+
+    pub fn reseed(&mut self, seed: u64) {
+        self.seed = seed;
+        self.rng = SmallRng::seed_from_u64(seed);
+    }
+
+    /// Starts recording the placements as fumen pages. Panics if the board
+    /// doesn't fit a fumen field.
+    pub fn record_fumen(&mut self) {
+        fumen::check_board(self.pos.board()).unwrap();
+        self.pages = Some(Vec::new());
+    }
+
+    /// The recorded game as a fumen, a page per placement and one for the
+    /// board it ended on.
+    pub fn fumen(&self) -> Option<String> {
+        let mut pages = self.pages.clone()?;
+        pages.push(Page::of(&self.pos, None));
+
+        Some(fumen::encode(&pages))
+    }
+
+    pub fn play(&mut self, rules: &Rules, placement: Placement) {
+        if let Some(pages) = &mut self.pages {
+            pages.push(Page::of(&self.pos, Some(placement)));
+        }
+        self.pos = self.pos.apply_move(rules, placement, Some(&mut self.rng));
+        self.pieces += 1;
+    }
+
+    /// Lets `search` pick and play one move, returning it with its input
+    /// sequence, or `None` once the game is over and `game_over` is set.
+    pub fn step(
+        &mut self,
+        search: &mut Search,
+        net: &mut FeedForwardNetwork,
+        rules: &Rules,
+    ) -> Result<Option<BestMove>, NetError> {
+        self.game_over = self.pos.is_game_over(rules);
+        if self.game_over.is_some() {
+            return Ok(None);
+        }
+
+        let best_move = search.run(net, &self.pos)?;
+        match &best_move {
+            Some(best_move) => self.play(rules, best_move.placement),
+            None => self.game_over = Some(GameOver::NoMoves),
+        }
+
+        Ok(best_move)
+    }
+
+    /// Plays until the game ends or `max_pieces` pieces have been placed.
+    pub fn play_out(
+        &mut self,
+        search: &mut Search,
+        net: &mut FeedForwardNetwork,
+        rules: &Rules,
+        max_pieces: usize,
+    ) -> Result<(), NetError> {
+        while self.pieces < max_pieces {
+            if self.step(search, net, rules)?.is_none() {
+                break;
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn summary(&self) -> GameSummary {
+        GameSummary {
+            seed: self.seed,
+            score: self.pos.score,
+            lines: self.pos.lines,
+            level: self.pos.level,
+            pieces: self.pieces,
+            game_over: self.game_over,
+        }
+    }
+}
+
+/// Plays one game from `start` per seed, spreading the games over
+/// `options.threads` workers that each own a search and a copy of `net`.
+pub fn play_games(
+    start: &Position,
+    seeds: &[u64],
+    net: &FeedForwardNetwork,
+    options: &Options,
+) -> Result<Vec<GameSummary>, NetError> {
+    run_games(start, seeds, net, options, false, Game::summary)
+}
+
+/// Like `play_games`, also exporting every game as a fumen. Panics if the
+/// board doesn't fit a fumen field.
+pub fn record_games(
+    start: &Position,
+    seeds: &[u64],
+    net: &FeedForwardNetwork,
+    options: &Options,
+) -> Result<Vec<(GameSummary, String)>, NetError> {
+    run_games(start, seeds, net, options, true, |game| {
+        (game.summary(), game.fumen().unwrap())
+    })
+}
+
+fn run_games<R: Send>(
+    start: &Position,
+    seeds: &[u64],
+    net: &FeedForwardNetwork,
+    options: &Options,
+    record: bool,
+    finish: impl Fn(&Game) -> R + Sync,
+) -> Result<Vec<R>, NetError> {
+    let start = start_position(start, options);
+
+    run_parallel(
+        seeds.len(),
+        options.threads,
+        |workers| {
+            let options = options.shared_by(workers);
+            (Search::new(&options), net.clone(), options.rules())
+        },
+        |(search, net, rules), i| {
+            let mut game = Game::new(start.clone(), seeds[i]);
+            game.set_preview(options.previews);
+            if record {
+                game.record_fumen();
+            }
+            game.play_out(search, net, rules, options.max_pieces)
+                .map(|_| finish(&game))
+        },
+    )
+    .into_iter()
+    .collect()
+}
+
+/// `start` set up for the games of `options`.
+pub fn start_position(start: &Position, options: &Options) -> Position {
+    let mut start = start.clone();
+    start.set_randomizer(options.randomizer);
+    // Games never run below the start level
+    start.level = start.level.max(options.start_level);
+
+    start
+}
+
+/// Runs `job` for every index below `len` on `threads` workers, 0 meaning
+/// one per core, each with its own state made by `init` from the number of
+/// workers. The results come back in index order.
+pub fn run_parallel<S, R: Send>(
+    len: usize,
+    threads: usize,
+    init: impl Fn(usize) -> S + Sync,
+    job: impl Fn(&mut S, usize) -> R + Sync,
+) -> Vec<R> {
+    let workers = match threads {
+        0 => thread::available_parallelism().map_or(1, |n| n.get()),
+        n => n,
+    }
+    .min(len);
+
+    let next = AtomicUsize::new(0);
+    let results = Mutex::new((0..len).map(|_| None).collect::<Vec<_>>());
+
+    thread::scope(|scope| {
+        for _ in 0..workers {
+            scope.spawn(|| {
+                let mut state = init(workers);
+
+                loop {
+                    let i = next.fetch_add(1, Ordering::Relaxed);
+                    if i >= len {
+                        break;
+                    }
+
+                    let result = job(&mut state, i);
+                    results.lock().unwrap()[i] = Some(result);
+                }
+            });
+        }
+    });
+
+    results
+        .into_inner()
+        .unwrap()
+        .into_iter()
+        .map(|result| result.unwrap())
+        .collect()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct GameSummary {
+    pub seed: u64,
+    pub score: i64,
+    pub lines: usize,
+    pub level: usize,
+    pub pieces: usize,
+    /// `None` when the game stopped at the piece limit.
+    pub game_over: Option<GameOver>,
+}
+
+/// Aggregate of the scores of several games.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct Stats {
+    pub mean: f64,
+    pub median: f64,
+    pub min: i64,
+    pub max: i64,
+    pub std_dev: f64,
+}
+
+impl Stats {
+    pub fn from_scores(scores: &[i64]) -> Option<Stats> {
+        if scores.is_empty() {
+            return None;
+        }
+
+        let mut sorted = scores.to_vec();
+        sorted.sort_unstable();
+
+        let n = sorted.len();
+        let mean = sorted.iter().sum::<i64>() as f64 / n as f64;
+        let median = if n.is_multiple_of(2) {
+            (sorted[n / 2 - 1] + sorted[n / 2]) as f64 / 2.
+        } else {
+            sorted[n / 2] as f64
+        };
+        let variance = sorted
+            .iter()
+            .map(|&score| (score as f64 - mean).powi(2))
+            .sum::<f64>()
+            / n as f64;
+
+        Some(Stats {
+            mean,
+            median,
+            min: sorted[0],
+            max: sorted[n - 1],
+            std_dev: variance.sqrt(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::net::tests::sample_net;
+    use std::str::FromStr;
+
+    fn play(seed: u64) -> (i64, String) {
+        let options = Options {
+            max_depth: 2,
+            ..Options::default()
+        };
+        let mut search = Search::new(&options);
+        let mut game = Game::new(Position::default(), seed);
+
+        game.play_out(&mut search, &mut sample_net(), &options.rules(), 10)
+            .unwrap();
+
+        (game.pos.score, game.pos.to_string())
+    }
+
+    #[test]
+    fn test_same_seed_same_game() {
+        assert_eq!(play(42), play(42));
+    }
+
+    #[test]
+    fn test_piece_sequence_depends_on_seed() {
+        let deal = |seed| {
+            let mut game = Game::new(Position::default(), seed);
+            (0..20)
+                .map(|_| game.pos.randomizer.deal(&mut game.rng))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(deal(1), deal(1));
+        assert_ne!(deal(1), deal(2));
+    }
+
+    #[test]
+    fn test_parallel_matches_sequential() {
+        let seeds = [1, 2, 3, 4, 5];
+        let options = Options {
+            max_depth: 2,
+            max_pieces: 8,
+            ..Options::default()
+        };
+        let sequential = play_games(&Position::default(), &seeds, &sample_net(), &options).unwrap();
+
+        let options = Options {
+            threads: 3,
+            ..options
+        };
+        let parallel = play_games(&Position::default(), &seeds, &sample_net(), &options).unwrap();
+
+        assert_eq!(sequential, parallel);
+        assert_eq!(parallel[2].seed, 3);
+    }
+
+    #[test]
+    fn test_preview_queue() {
+        let options = Options {
+            max_depth: 2,
+            max_pieces: 3,
+            previews: 5,
+            ..Options::default()
+        };
+        let mut game = Game::new(Position::default(), 9);
+        game.set_preview(options.previews);
+        assert_eq!(game.pos.queue.len(), 5);
+
+        let queue = game.pos.queue.clone();
+        let mut search = Search::new(&options);
+        game.play_out(&mut search, &mut sample_net(), &options.rules(), 1)
+            .unwrap();
+
+        assert_eq!(game.pos.current_piece, queue[0]);
+        assert_eq!(game.pos.queue[..4], queue[1..]);
+        assert_eq!(game.pos.queue.len(), 5);
+    }
+
+    #[test]
+    fn test_game_over_reason() {
+        let options = Options {
+            max_depth: 2,
+            ..Options::default()
+        };
+        let tpn = format!("////{} I J 0 -", "1Z1Z1Z1Z1Z/".repeat(18));
+        let mut search = Search::new(&options);
+        let mut game = Game::new(Position::from_str(&tpn).unwrap(), 3);
+
+        game.play_out(&mut search, &mut sample_net(), &options.rules(), 100)
+            .unwrap();
+
+        let summary = game.summary();
+        assert!(summary.pieces < 100);
+        assert!(summary.game_over.is_some());
+        assert_eq!(game.pos.is_game_over(&options.rules()), summary.game_over);
+    }
+
+    #[test]
+    fn test_stats() {
+        assert_eq!(Stats::from_scores(&[]), None);
+
+        let stats = Stats::from_scores(&[40, 0, 100, 300]).unwrap();
+        assert_eq!(stats.mean, 110.);
+        assert_eq!(stats.median, 70.);
+        assert_eq!(stats.min, 0);
+        assert_eq!(stats.max, 300);
+        assert!((stats.std_dev - 115.325626).abs() < 1e-6);
+
+        assert_eq!(Stats::from_scores(&[5, 1, 3]).unwrap().median, 3.);
+    }
+}
@@ -0,0 +1,176 @@
+use std::{fmt, str::FromStr};
+
+use arrayvec::ArrayVec;
+
+use crate::scoring::{Clear, Spin};
+
+/// Batches of incoming garbage a position can hold before new ones get
+/// merged into the last.
+pub const MAX_PENDING: usize = 16;
+
+/// Garbage rows sent by one clear, all with the hole in the same column.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Garbage {
+    pub lines: usize,
+    pub hole: usize,
+}
+
+/// Garbage received but not yet risen into the board, oldest first.
+pub type GarbageQueue = ArrayVec<Garbage, MAX_PENDING>;
+
+impl fmt::Display for Garbage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}@{}", self.lines, self.hole)
+    }
+}
+
+impl FromStr for Garbage {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (lines, hole) = s.split_once('@').ok_or(())?;
+
+        Ok(Garbage {
+            lines: lines.parse().map_err(|_| ())?,
+            hole: hole.parse().map_err(|_| ())?,
+        })
+    }
+}
+
+const ATTACK_LINES: [usize; 5] = [0, 0, 1, 2, 4];
+const ATTACK_MINI: [usize; 3] = [0, 0, 1];
+const ATTACK_SPIN: [usize; 4] = [0, 2, 4, 6];
+/// Bonus by combo, the first clear of a chain being combo 1.
+const ATTACK_COMBO: [usize; 13] = [0, 0, 1, 1, 1, 2, 2, 3, 3, 4, 4, 4, 5];
+const ATTACK_BACK_TO_BACK: usize = 1;
+const ATTACK_PERFECT: usize = 10;
+
+/// Lines `clear` sends in the guideline attack table, before cancelling.
+/// `combo` and `back_to_back` are as in `ScoringSystem::points`.
+pub fn attack(clear: &Clear, combo: usize, back_to_back: bool) -> usize {
+    if clear.lines == 0 {
+        return 0;
+    }
+
+    let mut lines = match clear.spin {
+        Spin::None => ATTACK_LINES[clear.lines],
+        Spin::Mini => ATTACK_MINI[clear.lines.min(2)],
+        Spin::Full => ATTACK_SPIN[clear.lines.min(3)],
+    };
+
+    if back_to_back && clear.is_difficult() {
+        lines += ATTACK_BACK_TO_BACK;
+    }
+
+    if clear.perfect {
+        lines += ATTACK_PERFECT;
+    }
+
+    lines + ATTACK_COMBO[combo.saturating_sub(1).min(ATTACK_COMBO.len() - 1)]
+}
+
+/// Uses `attack` lines to cancel `pending` garbage, oldest first, and
+/// returns the lines left to send.
+pub fn cancel(pending: &mut GarbageQueue, mut attack: usize) -> usize {
+    while attack > 0 {
+        let Some(garbage) = pending.first_mut() else {
+            break;
+        };
+
+        let cancelled = attack.min(garbage.lines);
+        garbage.lines -= cancelled;
+        attack -= cancelled;
+
+        if garbage.lines == 0 {
+            pending.remove(0);
+        }
+    }
+
+    attack
+}
+
+/// Queues `garbage` behind `pending`, adding it to the last batch once the
+/// queue is full. No batch grows past `max_lines`, which already rises the
+/// whole board away.
+pub fn receive(pending: &mut GarbageQueue, garbage: Garbage, max_lines: usize) {
+    let lines = garbage.lines.min(max_lines);
+    if lines == 0 {
+        return;
+    }
+
+    if let Err(err) = pending.try_push(Garbage { lines, ..garbage }) {
+        let last = pending.last_mut().unwrap();
+        last.lines = last
+            .lines
+            .saturating_add(err.element().lines)
+            .min(max_lines);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn clear(lines: usize, spin: Spin) -> Clear {
+        Clear {
+            lines,
+            spin,
+            perfect: false,
+        }
+    }
+
+    #[test]
+    fn test_attack() {
+        assert_eq!(attack(&clear(0, Spin::None), 0, false), 0);
+        assert_eq!(attack(&clear(1, Spin::None), 1, false), 0);
+        assert_eq!(attack(&clear(4, Spin::None), 1, false), 4);
+        assert_eq!(attack(&clear(4, Spin::None), 1, true), 5);
+        assert_eq!(attack(&clear(2, Spin::Full), 1, false), 4);
+        assert_eq!(attack(&clear(1, Spin::Mini), 1, true), 1);
+
+        // A double as the fourth clear in a row, then far into a combo
+        assert_eq!(attack(&clear(2, Spin::None), 4, false), 2);
+        assert_eq!(attack(&clear(1, Spin::None), 30, false), 5);
+
+        let perfect = Clear {
+            perfect: true,
+            ..clear(1, Spin::None)
+        };
+        assert_eq!(attack(&perfect, 1, false), 10);
+    }
+
+    #[test]
+    fn test_cancel() {
+        let mut pending = GarbageQueue::new();
+        receive(&mut pending, Garbage { lines: 2, hole: 0 }, 22);
+        receive(&mut pending, Garbage { lines: 0, hole: 1 }, 22);
+        receive(&mut pending, Garbage { lines: 3, hole: 5 }, 22);
+        assert_eq!(pending.len(), 2);
+
+        assert_eq!(cancel(&mut pending, 3), 0);
+        assert_eq!(pending.as_slice(), [Garbage { lines: 2, hole: 5 }]);
+
+        assert_eq!(cancel(&mut pending, 4), 2);
+        assert!(pending.is_empty());
+    }
+
+    #[test]
+    fn test_receive_when_full() {
+        let mut pending = GarbageQueue::new();
+        for hole in 0..MAX_PENDING + 2 {
+            receive(&mut pending, Garbage { lines: 1, hole }, 22);
+        }
+
+        assert_eq!(pending.len(), MAX_PENDING);
+        assert_eq!(pending.last().unwrap().lines, 3);
+
+        let huge = Garbage {
+            lines: usize::MAX,
+            hole: 0,
+        };
+        receive(&mut pending, huge, 22);
+        receive(&mut pending, huge, 22);
+        assert_eq!(pending.last().unwrap().lines, 22);
+        assert_eq!("3@4".parse(), Ok(Garbage { lines: 3, hole: 4 }));
+    }
+}
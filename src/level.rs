@@ -0,0 +1,107 @@
+use std::{fmt, str::FromStr};
+
+/// How the level goes up with cleared lines. Levels count from 0 in both
+/// systems, so the guideline's level 1 is level 0 here.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LevelSystem {
+    /// The first level up waits longer when starting above level 9, then
+    /// comes every 10 lines.
+    Nes,
+    /// A level every 10 lines.
+    Guideline,
+}
+
+pub const LEVEL_SYSTEM_NAMES: [&str; 2] = ["nes", "guideline"];
+
+impl fmt::Display for LevelSystem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            LevelSystem::Nes => "nes",
+            LevelSystem::Guideline => "guideline",
+        };
+
+        write!(f, "{}", name)
+    }
+}
+
+impl FromStr for LevelSystem {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "nes" => Ok(LevelSystem::Nes),
+            "guideline" => Ok(LevelSystem::Guideline),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Frames per row on NTSC NES, from level 0 to 29 and above.
+const NES_GRAVITY: [u32; 30] = [
+    48, 43, 38, 33, 28, 23, 18, 13, 8, 6, 5, 5, 5, 4, 4, 4, 3, 3, 3, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2,
+    1,
+];
+
+impl LevelSystem {
+    /// Level reached after clearing `lines` lines from `start_level`.
+    pub fn level(&self, start_level: usize, lines: usize) -> usize {
+        match self {
+            LevelSystem::Nes => {
+                let first =
+                    (start_level * 10 + 10).min((start_level * 10).saturating_sub(50).max(100));
+                if lines < first {
+                    start_level
+                } else {
+                    start_level + 1 + (lines - first) / 10
+                }
+            }
+            LevelSystem::Guideline => start_level + lines / 10,
+        }
+    }
+
+    /// Frames per row of gravity at `level`.
+    pub fn gravity(&self, level: usize) -> u32 {
+        match self {
+            LevelSystem::Nes => NES_GRAVITY[level.min(NES_GRAVITY.len() - 1)],
+            LevelSystem::Guideline => {
+                let level = level.min(30) as f64;
+                let seconds = (0.8 - level * 0.007).powf(level);
+                ((seconds * 60.).round() as u32).max(1)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_nes_transition() {
+        let nes = LevelSystem::Nes;
+
+        assert_eq!(nes.level(0, 9), 0);
+        assert_eq!(nes.level(0, 10), 1);
+        assert_eq!(nes.level(0, 35), 3);
+
+        // Starting at 18 the first level up takes 130 lines
+        assert_eq!(nes.level(18, 129), 18);
+        assert_eq!(nes.level(18, 130), 19);
+        assert_eq!(nes.level(18, 140), 20);
+
+        assert_eq!(nes.level(9, 99), 9);
+        assert_eq!(nes.level(9, 100), 10);
+    }
+
+    #[test]
+    fn test_gravity() {
+        assert_eq!(LevelSystem::Nes.gravity(0), 48);
+        assert_eq!(LevelSystem::Nes.gravity(19), 2);
+        assert_eq!(LevelSystem::Nes.gravity(40), 1);
+
+        assert_eq!(LevelSystem::Guideline.gravity(0), 60);
+        assert_eq!(LevelSystem::Guideline.gravity(1), 48);
+        assert_eq!(LevelSystem::Guideline.gravity(20), 1);
+        assert_eq!(LevelSystem::Guideline.level(0, 25), 2);
+    }
+}
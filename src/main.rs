@@ -1,8 +1,20 @@
 mod comm;
+mod fumen;
+mod game;
+mod garbage;
+mod level;
 mod net;
+mod options;
+mod piece;
 mod pos;
+mod randomizer;
+mod rotation;
+mod scoring;
 mod search;
+mod timeline;
+mod tpn;
 mod transposition;
+mod versus;
 
 fn main() {
     comm::start().unwrap();
@@ -1,38 +1,82 @@
-use rustc_hash::FxHashMap;
+use std::fmt;
 
+use rustc_hash::{FxHashMap, FxHashSet};
+
+pub type NodeEval = (i64, f64, f64, Vec<(i64, f64)>);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NetError {
+    InputCount { expected: usize, got: usize },
+    NoOutputs,
+    UnknownLink { node: i64, source: i64 },
+}
+
+impl fmt::Display for NetError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NetError::InputCount { expected, got } => {
+                write!(f, "Expected {} inputs, got {}", expected, got)
+            }
+            NetError::NoOutputs => write!(f, "Network has no outputs"),
+            NetError::UnknownLink { node, source } => write!(
+                f,
+                "Node {} links from {}, which is neither an input nor an earlier node",
+                node, source
+            ),
+        }
+    }
+}
+
+#[derive(Clone)]
 pub struct FeedForwardNetwork {
     input_nodes: Vec<i64>,
     output_nodes: Vec<i64>,
-    node_evals: Vec<(i64, f64, f64, Vec<(i64, f64)>)>,
+    node_evals: Vec<NodeEval>,
     values: FxHashMap<i64, f64>,
 }
 
 impl FeedForwardNetwork {
+    /// Nodes are evaluated in order, so each may only link from the inputs
+    /// and the nodes before it.
     pub fn new(
         inputs: Vec<i64>,
         outputs: Vec<i64>,
-        node_evals: Vec<(i64, f64, f64, Vec<(i64, f64)>)>,
-    ) -> Self {
+        node_evals: Vec<NodeEval>,
+    ) -> Result<Self, NetError> {
+        if outputs.is_empty() {
+            return Err(NetError::NoOutputs);
+        }
+
+        let mut known: FxHashSet<i64> = inputs.iter().copied().collect();
+        for (node, _, _, links) in &node_evals {
+            if let Some(&(source, _)) = links.iter().find(|(i, _)| !known.contains(i)) {
+                return Err(NetError::UnknownLink {
+                    node: *node,
+                    source,
+                });
+            }
+            known.insert(*node);
+        }
+
         let mut values = FxHashMap::default();
         for key in inputs.iter().chain(outputs.iter()) {
-            values.insert(*key, 0.0 as f64);
+            values.insert(*key, 0.0);
         }
 
-        FeedForwardNetwork {
+        Ok(FeedForwardNetwork {
             input_nodes: inputs,
             output_nodes: outputs,
             node_evals,
             values,
-        }
+        })
     }
 
-    pub fn activate(&mut self, inputs: Vec<f64>) -> Vec<f64> {
+    pub fn activate(&mut self, inputs: Vec<f64>) -> Result<Vec<f64>, NetError> {
         if self.input_nodes.len() != inputs.len() {
-            panic!(
-                "Expected {} inputs, got {}",
-                self.input_nodes.len(),
-                inputs.len()
-            );
+            return Err(NetError::InputCount {
+                expected: self.input_nodes.len(),
+                got: inputs.len(),
+            });
         }
 
         for (key, value) in self.input_nodes.iter().zip(inputs) {
@@ -45,9 +89,56 @@ impl FeedForwardNetwork {
             self.values.insert(*node, (bias + response * s).tanh());
         }
 
-        self.output_nodes
+        Ok(self
+            .output_nodes
             .iter()
             .map(|&i| *self.values.get(&i).unwrap())
-            .collect()
+            .collect())
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    /// Inputs, outputs and nodes of a small network weighing the three
+    /// position features.
+    pub fn sample_parts() -> (Vec<i64>, Vec<i64>, Vec<NodeEval>) {
+        (
+            vec![-1, -2, -3],
+            vec![0],
+            vec![(0, 0., 1., vec![(-1, -0.5), (-2, -0.2), (-3, -0.5)])],
+        )
+    }
+
+    pub fn sample_net() -> FeedForwardNetwork {
+        let (inputs, outputs, node_evals) = sample_parts();
+        FeedForwardNetwork::new(inputs, outputs, node_evals).unwrap()
+    }
+
+    #[test]
+    fn test_new() {
+        let (inputs, _, node_evals) = sample_parts();
+        assert_eq!(
+            FeedForwardNetwork::new(inputs.clone(), vec![], node_evals).err(),
+            Some(NetError::NoOutputs)
+        );
+
+        // Node 1 is only evaluated after node 0 reads it
+        let node_evals = vec![(0, 0., 1., vec![(1, 1.)]), (1, 0., 1., vec![(-1, 1.)])];
+        assert_eq!(
+            FeedForwardNetwork::new(inputs, vec![0], node_evals).err(),
+            Some(NetError::UnknownLink { node: 0, source: 1 })
+        );
+
+        let mut net = sample_net();
+        assert_eq!(
+            net.activate(vec![0.; 2]),
+            Err(NetError::InputCount {
+                expected: 3,
+                got: 2
+            })
+        );
+        assert_eq!(net.activate(vec![0.; 3]), Ok(vec![0.]));
     }
 }
@@ -0,0 +1,381 @@
+use std::fmt;
+
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::{
+    level::{LevelSystem, LEVEL_SYSTEM_NAMES},
+    pos::{Rules, MAX_PREVIEW},
+    randomizer::{RandomizerKind, RANDOMIZER_NAMES},
+    rotation::{RotationSystem, ROTATION_SYSTEM_NAMES},
+    scoring::{ScoringSystem, SCORING_SYSTEM_NAMES},
+    timeline::Timing,
+};
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "kind")]
+pub enum OptionType {
+    Int {
+        default: i64,
+        min: i64,
+        max: i64,
+    },
+    Bool {
+        default: bool,
+    },
+    Choice {
+        default: &'static str,
+        choices: &'static [&'static str],
+    },
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct OptionInfo {
+    pub name: &'static str,
+    #[serde(flatten)]
+    pub ty: OptionType,
+    pub value: Value,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OptionError {
+    UnknownOption(String),
+    InvalidValue { name: String, value: String },
+}
+
+impl fmt::Display for OptionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OptionError::UnknownOption(name) => write!(f, "Unknown option {}", name),
+            OptionError::InvalidValue { name, value } => {
+                write!(f, "Invalid value {} for option {}", value, name)
+            }
+        }
+    }
+}
+
+const SPECS: [(&str, OptionType); 28] = [
+    ("min_depth", int(2, 1, 6)),
+    ("max_depth", int(3, 1, 6)),
+    ("hash_size", int(16384, 1, 1 << 24)),
+    ("max_pieces", int(1000, 1, 1_000_000)),
+    ("threads", int(1, 0, 64)),
+    ("score_single", int(40, 0, 1_000_000)),
+    ("score_double", int(100, 0, 1_000_000)),
+    ("score_triple", int(300, 0, 1_000_000)),
+    ("score_tetris", int(1200, 0, 1_000_000)),
+    ("scoring", choice("classic", &SCORING_SYSTEM_NAMES)),
+    ("randomizer", choice("nes", &RANDOMIZER_NAMES)),
+    ("hold", OptionType::Bool { default: false }),
+    ("previews", int(1, 0, MAX_PREVIEW as i64)),
+    ("rotation", choice("classic", &ROTATION_SYSTEM_NAMES)),
+    ("prefer_hard_drop", OptionType::Bool { default: false }),
+    ("finesse", OptionType::Bool { default: false }),
+    ("das", OptionType::Bool { default: false }),
+    ("timeline", OptionType::Bool { default: false }),
+    ("gravity", int(48, 1, 1000)),
+    ("das_frames", int(16, 0, 1000)),
+    ("arr_frames", int(6, 0, 1000)),
+    ("input_interval", int(2, 1, 1000)),
+    ("lock_delay", int(0, 0, 1000)),
+    ("levels", choice("nes", &LEVEL_SYSTEM_NAMES)),
+    ("start_level", int(0, 0, 99)),
+    ("level_gravity", OptionType::Bool { default: false }),
+    ("top_out", OptionType::Bool { default: false }),
+    ("garbage", OptionType::Bool { default: false }),
+];
+
+const fn int(default: i64, min: i64, max: i64) -> OptionType {
+    OptionType::Int { default, min, max }
+}
+
+const fn choice(default: &'static str, choices: &'static [&'static str]) -> OptionType {
+    OptionType::Choice { default, choices }
+}
+
+/// Runtime tunables, changed through `SetOption`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Options {
+    pub min_depth: usize,
+    pub max_depth: usize,
+    /// Transposition table entries, shared out when several searches run
+    /// at once.
+    pub hash_size: usize,
+    pub max_pieces: usize,
+    /// Worker threads for multi-game `PlayGame`, 0 meaning one per core.
+    pub threads: usize,
+    pub line_scores: [i64; 4],
+    pub scoring: ScoringSystem,
+    pub randomizer: RandomizerKind,
+    pub hold: bool,
+    /// Preview length of games started by `PlayGame`.
+    pub previews: usize,
+    pub rotation: RotationSystem,
+    pub prefer_hard_drop: bool,
+    pub finesse: bool,
+    pub das: bool,
+    /// Schedule moves frame by frame with the timings below.
+    pub timeline: bool,
+    pub timing: Timing,
+    pub levels: LevelSystem,
+    pub start_level: usize,
+    /// Take the gravity of the timeline from the current level.
+    pub level_gravity: bool,
+    /// End games once blocks reach the top row.
+    pub top_out: bool,
+    /// Send garbage with line clears, for versus play.
+    pub garbage: bool,
+}
+
+impl Options {
+    /// The options of one of `searches` searches running at once, which
+    /// get an equal share of the hash table.
+    pub fn shared_by(&self, searches: usize) -> Options {
+        Options {
+            hash_size: (self.hash_size / searches).max(1),
+            ..self.clone()
+        }
+    }
+
+    pub fn rules(&self) -> Rules {
+        Rules {
+            line_scores: self.line_scores,
+            scoring: self.scoring,
+            hold: self.hold,
+            rotation: self.rotation,
+            prefer_hard_drop: self.prefer_hard_drop,
+            finesse: self.finesse,
+            das: self.das,
+            timing: self.timeline.then_some(self.timing),
+            levels: self.levels,
+            start_level: self.start_level,
+            level_gravity: self.level_gravity,
+            top_out: self.top_out,
+            garbage: self.garbage,
+        }
+    }
+
+    pub fn list(&self) -> Vec<OptionInfo> {
+        SPECS
+            .iter()
+            .map(|(name, ty)| OptionInfo {
+                name,
+                ty: ty.clone(),
+                value: self.get(name).unwrap(),
+            })
+            .collect()
+    }
+
+    pub fn get(&self, name: &str) -> Option<Value> {
+        let value = match name {
+            "min_depth" => self.min_depth.into(),
+            "max_depth" => self.max_depth.into(),
+            "hash_size" => self.hash_size.into(),
+            "max_pieces" => self.max_pieces.into(),
+            "threads" => self.threads.into(),
+            "score_single" => self.line_scores[0].into(),
+            "score_double" => self.line_scores[1].into(),
+            "score_triple" => self.line_scores[2].into(),
+            "score_tetris" => self.line_scores[3].into(),
+            "scoring" => self.scoring.to_string().into(),
+            "randomizer" => self.randomizer.to_string().into(),
+            "hold" => self.hold.into(),
+            "previews" => self.previews.into(),
+            "rotation" => self.rotation.to_string().into(),
+            "prefer_hard_drop" => self.prefer_hard_drop.into(),
+            "finesse" => self.finesse.into(),
+            "das" => self.das.into(),
+            "timeline" => self.timeline.into(),
+            "gravity" => self.timing.gravity.into(),
+            "das_frames" => self.timing.das.into(),
+            "arr_frames" => self.timing.arr.into(),
+            "input_interval" => self.timing.input_interval.into(),
+            "lock_delay" => self.timing.lock_delay.into(),
+            "levels" => self.levels.to_string().into(),
+            "start_level" => self.start_level.into(),
+            "level_gravity" => self.level_gravity.into(),
+            "top_out" => self.top_out.into(),
+            "garbage" => self.garbage.into(),
+            _ => return None,
+        };
+
+        Some(value)
+    }
+
+    pub fn set(&mut self, name: &str, value: &Value) -> Result<(), OptionError> {
+        let (_, ty) = SPECS
+            .iter()
+            .find(|(spec_name, _)| *spec_name == name)
+            .ok_or_else(|| OptionError::UnknownOption(name.to_string()))?;
+
+        let invalid = || OptionError::InvalidValue {
+            name: name.to_string(),
+            value: value.to_string(),
+        };
+
+        match *ty {
+            OptionType::Int { min, max, .. } => {
+                let v = value
+                    .as_i64()
+                    .filter(|v| (min..=max).contains(v))
+                    .ok_or_else(invalid)?;
+
+                match name {
+                    "min_depth" => self.min_depth = v as usize,
+                    "max_depth" => self.max_depth = v as usize,
+                    "hash_size" => self.hash_size = v as usize,
+                    "max_pieces" => self.max_pieces = v as usize,
+                    "threads" => self.threads = v as usize,
+                    "previews" => self.previews = v as usize,
+                    "score_single" => self.line_scores[0] = v,
+                    "score_double" => self.line_scores[1] = v,
+                    "score_triple" => self.line_scores[2] = v,
+                    "score_tetris" => self.line_scores[3] = v,
+                    "gravity" => self.timing.gravity = v as u32,
+                    "das_frames" => self.timing.das = v as u32,
+                    "arr_frames" => self.timing.arr = v as u32,
+                    "input_interval" => self.timing.input_interval = v as u32,
+                    "lock_delay" => self.timing.lock_delay = v as u32,
+                    "start_level" => self.start_level = v as usize,
+                    _ => unreachable!(),
+                }
+            }
+            OptionType::Bool { .. } => {
+                let v = value.as_bool().ok_or_else(invalid)?;
+
+                match name {
+                    "hold" => self.hold = v,
+                    "prefer_hard_drop" => self.prefer_hard_drop = v,
+                    "finesse" => self.finesse = v,
+                    "das" => self.das = v,
+                    "timeline" => self.timeline = v,
+                    "level_gravity" => self.level_gravity = v,
+                    "top_out" => self.top_out = v,
+                    "garbage" => self.garbage = v,
+                    _ => unreachable!(),
+                }
+            }
+            OptionType::Choice { choices, .. } => {
+                let v = value
+                    .as_str()
+                    .filter(|v| choices.contains(v))
+                    .ok_or_else(invalid)?;
+
+                match name {
+                    "randomizer" => self.randomizer = v.parse().unwrap(),
+                    "rotation" => self.rotation = v.parse().unwrap(),
+                    "scoring" => self.scoring = v.parse().unwrap(),
+                    "levels" => self.levels = v.parse().unwrap(),
+                    _ => unreachable!(),
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        let mut options = Options {
+            min_depth: 0,
+            max_depth: 0,
+            hash_size: 0,
+            max_pieces: 0,
+            threads: 0,
+            line_scores: [0; 4],
+            scoring: ScoringSystem::Classic,
+            randomizer: RandomizerKind::Nes,
+            hold: false,
+            previews: 0,
+            rotation: RotationSystem::Classic,
+            prefer_hard_drop: false,
+            finesse: false,
+            das: false,
+            timeline: false,
+            timing: Timing {
+                gravity: 0,
+                das: 0,
+                arr: 0,
+                input_interval: 0,
+                lock_delay: 0,
+            },
+            levels: LevelSystem::Nes,
+            start_level: 0,
+            level_gravity: false,
+            top_out: false,
+            garbage: false,
+        };
+
+        for (name, ty) in SPECS.iter() {
+            let default = match *ty {
+                OptionType::Int { default, .. } => Value::from(default),
+                OptionType::Bool { default } => Value::from(default),
+                OptionType::Choice { default, .. } => Value::from(default),
+            };
+            options.set(name, &default).unwrap();
+        }
+
+        options
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_defaults() {
+        let options = Options::default();
+
+        assert_eq!(options.min_depth, 2);
+        assert_eq!(options.max_depth, 3);
+        assert_eq!(options.hash_size, 16384);
+        assert_eq!(options.max_pieces, 1000);
+        assert_eq!(options.threads, 1);
+        assert_eq!(options.line_scores, [40, 100, 300, 1200]);
+        assert_eq!(options.list().len(), SPECS.len());
+    }
+
+    #[test]
+    fn test_set() {
+        let mut options = Options::default();
+
+        options.set("max_depth", &Value::from(4)).unwrap();
+        assert_eq!(options.max_depth, 4);
+
+        assert!(matches!(
+            options.set("max_depth", &Value::from(100)),
+            Err(OptionError::InvalidValue { .. })
+        ));
+        assert!(matches!(
+            options.set("max_depth", &Value::from("deep")),
+            Err(OptionError::InvalidValue { .. })
+        ));
+        assert!(matches!(
+            options.set("nope", &Value::from(1)),
+            Err(OptionError::UnknownOption(_))
+        ));
+        assert_eq!(options.max_depth, 4);
+
+        options.set("randomizer", &Value::from("bag")).unwrap();
+        assert_eq!(options.randomizer, RandomizerKind::Bag);
+        assert!(matches!(
+            options.set("randomizer", &Value::from("nope")),
+            Err(OptionError::InvalidValue { .. })
+        ));
+    }
+
+    #[test]
+    fn test_shared_by() {
+        let mut options = Options::default();
+        for name in ["hash_size", "threads"] {
+            assert!(options.set(name, &Value::from(1 << 26)).is_err());
+        }
+
+        assert_eq!(options.shared_by(4).hash_size, 4096);
+        options.hash_size = 3;
+        assert_eq!(options.shared_by(4).hash_size, 1);
+        assert_eq!(options.shared_by(4).max_depth, options.max_depth);
+    }
+}
@@ -0,0 +1,232 @@
+use std::fmt;
+
+use crate::pos::{Color, MAX_WIDTH};
+
+/// One of the seven tetrominoes. Unlike [`Color`], which also stands for
+/// empty cells and unknown pieces, every value names a real piece, so
+/// tables indexed by it can't be looked up out of range.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PieceKind {
+    I,
+    O,
+    J,
+    L,
+    S,
+    T,
+    Z,
+}
+
+impl PieceKind {
+    pub const ALL: [PieceKind; 7] = [
+        PieceKind::I,
+        PieceKind::O,
+        PieceKind::J,
+        PieceKind::L,
+        PieceKind::S,
+        PieceKind::T,
+        PieceKind::Z,
+    ];
+
+    /// Index of the piece in [`PieceKind::ALL`] and in the piece tables.
+    #[inline]
+    pub const fn index(self) -> usize {
+        self as usize
+    }
+
+    #[inline]
+    pub const fn color(self) -> Color {
+        match self {
+            PieceKind::I => Color::I,
+            PieceKind::O => Color::O,
+            PieceKind::J => Color::J,
+            PieceKind::L => Color::L,
+            PieceKind::S => Color::S,
+            PieceKind::T => Color::T,
+            PieceKind::Z => Color::Z,
+        }
+    }
+
+    /// Shapes of the piece, one per NES orientation.
+    #[inline]
+    pub fn shapes(self) -> &'static [Shape] {
+        SHAPES[self.index()]
+    }
+
+    /// Shape of the piece in rotation `rot`, wrapping around.
+    #[inline]
+    pub fn shape(self, rot: i32) -> &'static Shape {
+        let shapes = self.shapes();
+        &shapes[rot as usize % shapes.len()]
+    }
+}
+
+impl From<PieceKind> for Color {
+    fn from(piece: PieceKind) -> Self {
+        piece.color()
+    }
+}
+
+impl fmt::Display for PieceKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.color().fmt(f)
+    }
+}
+
+/// A piece in one orientation, as row bitmasks, bit 0 being its leftmost
+/// column, and as column bitmasks, bit 0 being its top row.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Shape {
+    /// Size of the bounding box.
+    pub width: usize,
+    pub height: usize,
+    pub rows: [u16; 4],
+    pub columns: [u64; 4],
+}
+
+impl Shape {
+    /// The shape with the four `(x, y)` cells, relative to the top left
+    /// corner of its bounding box.
+    const fn new(cells: [(usize, usize); 4]) -> Self {
+        let mut shape = Shape {
+            width: 0,
+            height: 0,
+            rows: [0; 4],
+            columns: [0; 4],
+        };
+
+        let mut i = 0;
+        while i < cells.len() {
+            let (x, y) = cells[i];
+            shape.rows[y] |= 1 << x;
+            shape.columns[x] |= 1 << y;
+            if x >= shape.width {
+                shape.width = x + 1;
+            }
+            if y >= shape.height {
+                shape.height = y + 1;
+            }
+            i += 1;
+        }
+
+        shape
+    }
+
+    /// The four `(x, y)` cells, relative to the top left corner of the
+    /// bounding box.
+    pub fn cells(&self) -> [(usize, usize); 4] {
+        let mut cells = [(0, 0); 4];
+        let mut i = 0;
+        for (y, &row) in self.rows[..self.height].iter().enumerate() {
+            for x in 0..self.width {
+                if row & (1 << x) != 0 {
+                    cells[i] = (x, y);
+                    i += 1;
+                }
+            }
+        }
+
+        cells
+    }
+
+    /// Bitset of the rows `y` where the shape at `(x, y)` overlaps a cell
+    /// of `columns`, every height being tested at once.
+    #[inline]
+    pub fn overlaps(&self, columns: &[u64; MAX_WIDTH], x: usize) -> u64 {
+        let mut overlaps = 0;
+        for (i, &shape_column) in self.columns[..self.width].iter().enumerate() {
+            let mut shape_column = shape_column;
+            while shape_column != 0 {
+                overlaps |= columns[x + i] >> shape_column.trailing_zeros();
+                shape_column &= shape_column - 1;
+            }
+        }
+
+        overlaps
+    }
+}
+
+#[rustfmt::skip]
+const I_SHAPES: [Shape; 2] = [
+    Shape::new([(0, 0), (1, 0), (2, 0), (3, 0)]),
+    Shape::new([(0, 0), (0, 1), (0, 2), (0, 3)]),
+];
+
+#[rustfmt::skip]
+const O_SHAPES: [Shape; 1] = [
+    Shape::new([(0, 0), (1, 0), (0, 1), (1, 1)]),
+];
+
+#[rustfmt::skip]
+const J_SHAPES: [Shape; 4] = [
+    Shape::new([(0, 0), (1, 0), (2, 0), (2, 1)]),
+    Shape::new([(1, 0), (1, 1), (0, 2), (1, 2)]),
+    Shape::new([(0, 0), (0, 1), (1, 1), (2, 1)]),
+    Shape::new([(0, 0), (1, 0), (0, 1), (0, 2)]),
+];
+
+#[rustfmt::skip]
+const L_SHAPES: [Shape; 4] = [
+    Shape::new([(0, 0), (1, 0), (2, 0), (0, 1)]),
+    Shape::new([(0, 0), (1, 0), (1, 1), (1, 2)]),
+    Shape::new([(2, 0), (0, 1), (1, 1), (2, 1)]),
+    Shape::new([(0, 0), (0, 1), (0, 2), (1, 2)]),
+];
+
+#[rustfmt::skip]
+const S_SHAPES: [Shape; 2] = [
+    Shape::new([(1, 0), (2, 0), (0, 1), (1, 1)]),
+    Shape::new([(0, 0), (0, 1), (1, 1), (1, 2)]),
+];
+
+#[rustfmt::skip]
+const T_SHAPES: [Shape; 4] = [
+    Shape::new([(0, 0), (1, 0), (2, 0), (1, 1)]),
+    Shape::new([(1, 0), (0, 1), (1, 1), (1, 2)]),
+    Shape::new([(1, 0), (0, 1), (1, 1), (2, 1)]),
+    Shape::new([(0, 0), (0, 1), (1, 1), (0, 2)]),
+];
+
+#[rustfmt::skip]
+const Z_SHAPES: [Shape; 2] = [
+    Shape::new([(0, 0), (1, 0), (1, 1), (2, 1)]),
+    Shape::new([(1, 0), (0, 1), (1, 1), (0, 2)]),
+];
+
+/// NES orientations of every piece, in [`PieceKind::ALL`] order.
+static SHAPES: [&[Shape]; 7] = [
+    &I_SHAPES, &O_SHAPES, &J_SHAPES, &L_SHAPES, &S_SHAPES, &T_SHAPES, &Z_SHAPES,
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_color_round_trip() {
+        for piece in PieceKind::ALL {
+            assert_eq!(piece.color().piece(), Some(piece));
+            assert_eq!(PieceKind::ALL[piece.index()], piece);
+        }
+        assert_eq!(Color::Empty.piece(), None);
+        assert_eq!(Color::Random.piece(), None);
+    }
+
+    #[test]
+    fn test_shapes() {
+        for piece in PieceKind::ALL {
+            for shape in piece.shapes() {
+                let cells: u32 = shape.rows.iter().map(|row| row.count_ones()).sum();
+                assert_eq!(cells, 4);
+                assert_ne!(shape.rows[shape.height - 1], 0);
+                assert_ne!(shape.columns[shape.width - 1], 0);
+            }
+        }
+
+        // T pointing down, as it spawns on the NES
+        let t = PieceKind::T.shape(0);
+        assert_eq!((t.width, t.height), (3, 2));
+        assert_eq!(t.rows[..2], [0b111, 0b010]);
+        assert_eq!(t.columns[..3], [0b01, 0b11, 0b01]);
+        assert_eq!(PieceKind::T.shape(4), t);
+    }
+}
@@ -1,51 +1,40 @@
+use crate::{
+    garbage::{self, Garbage, GarbageQueue},
+    level::LevelSystem,
+    piece::{PieceKind, Shape},
+    randomizer::{Randomizer, RandomizerKind},
+    rotation::{wrap_rot, RotationSystem, State},
+    scoring::{Clear, ScoringSystem, Spin},
+    timeline::Timing,
+};
 use arrayvec::ArrayVec;
-use lazy_static::lazy_static;
-use rand::{distributions::Distribution, rngs::SmallRng, Rng, SeedableRng};
-use rustc_hash::{FxHashMap, FxHashSet};
+use rand::{rngs::SmallRng, Rng};
+use rustc_hash::FxHashMap;
 use serde::Serialize;
 use std::{
     cmp::{self, Ordering},
     collections::BinaryHeap,
     fmt::{self, Debug},
-    str::FromStr,
+    ops::{Index, IndexMut},
 };
 
-use crate::comm;
-
-const BOARD_WIDTH: usize = 10;
-const BOARD_HEIGHT: usize = 22;
-const PIECE_NUMBER: usize = 7;
-
-type Board<T> = [[T; BOARD_WIDTH]; BOARD_HEIGHT];
-type Piece = Vec<Vec<Color>>;
-
-macro_rules! piece {
-    ($color:expr, $( $vec:expr ),* ) => {
-        {
-            let mut result = Vec::new();
-
-            $(
-                let mut new_piece = Vec::new();
-                for row in $vec.iter() {
-                    let mut new_row = Vec::new();
-                    for cell in row.iter() {
-                        match cell {
-                            0 => new_row.push(Color::Empty),
-                            1 => new_row.push($color),
-                            _ => panic!("Invalid value in the input vector"),
-                        }
-                    }
-                    new_piece.push(new_row);
-                }
-                result.push(new_piece);
-            )*
-
-            result
-        }
-    };
-}
-
-const PIECE_ARRAY: [Color; 7] = [
+/// Size of the default board, which the spawn tables are laid out for.
+pub const BOARD_WIDTH: usize = 10;
+pub const BOARD_HEIGHT: usize = 22;
+/// Largest board, fitting the 10x40 guideline matrix.
+pub const MAX_WIDTH: usize = 16;
+pub const MAX_HEIGHT: usize = 40;
+/// Smallest board side, fitting every piece in every rotation.
+pub const MIN_SIZE: usize = 4;
+/// Rows of the visible field on boards tall enough for it.
+pub const VISIBLE_HEIGHT: usize = 20;
+/// Rows above the visible field that pieces spawn in.
+pub const HIDDEN_ROWS: usize = 2;
+pub const MAX_PREVIEW: usize = 16;
+
+pub type Queue = ArrayVec<Color, MAX_PREVIEW>;
+
+pub const PIECE_ARRAY: [Color; 7] = [
     Color::I,
     Color::O,
     Color::J,
@@ -55,150 +44,34 @@ const PIECE_ARRAY: [Color; 7] = [
     Color::Z,
 ];
 
-lazy_static! {
-    #[rustfmt::skip]
-    static ref PIECES: [Vec<Piece>; PIECE_NUMBER] = [
-        piece!(
-            Color::I,
-            vec![
-                vec![1, 1, 1, 1]
-            ],
-            vec![
-                vec![1],
-                vec![1],
-                vec![1],
-                vec![1],
-            ]
-        ),
-        piece!(
-            Color::O,
-            vec![
-                vec![1, 1],
-                vec![1, 1]
-            ]
-        ),
-        piece!(
-            Color::J,
-            vec![
-                vec![1, 1, 1],
-                vec![0, 0, 1],
-            ],
-            vec![
-                vec![0, 1],
-                vec![0, 1],
-                vec![1, 1],
-            ],
-            vec![
-                vec![1, 0, 0],
-                vec![1, 1, 1],
-            ],
-            vec![
-                vec![1, 1],
-                vec![1, 0],
-                vec![1, 0],
-            ]
-        ),
-        piece!(
-            Color::L,
-            vec![
-                vec![1, 1, 1],
-                vec![1, 0, 0],
-            ],
-            vec![
-                vec![1, 1],
-                vec![0, 1],
-                vec![0, 1],
-            ],
-            vec![
-                vec![0, 0, 1],
-                vec![1, 1, 1],
-            ],
-            vec![
-                vec![1, 0],
-                vec![1, 0],
-                vec![1, 1],
-            ]
-        ),
-        piece!(
-            Color::S,
-            vec![
-                vec![0, 1, 1],
-                vec![1, 1, 0],
-            ],
-            vec![
-                vec![1, 0],
-                vec![1, 1],
-                vec![0, 1],
-            ]
-        ),
-        piece!(
-            Color::T,
-            vec![
-                vec![1, 1, 1],
-                vec![0, 1, 0],
-            ],
-            vec![
-                vec![0, 1],
-                vec![1, 1],
-                vec![0, 1],
-            ],
-            vec![
-                vec![0, 1, 0],
-                vec![1, 1, 1],
-            ],
-            vec![
-                vec![1, 0],
-                vec![1, 1],
-                vec![1, 0],
-            ]
-        ),
-        piece!(
-            Color::Z,
-            vec![
-                vec![1, 1, 0],
-                vec![0, 1, 1],
-            ],
-            vec![
-                vec![0, 1],
-                vec![1, 1],
-                vec![1, 0],
-            ]
-        ),
-    ];
-
-    static ref ROTATION_OFFSETS: Vec<Vec<(i32, i32)>> = vec![
-        vec![(2, -2), (-2, 2)],
-        vec![(0, 0)],
-        vec![(0, -1), (0, 0), (1, 0), (-1, 1)],
-        vec![(0, -1), (0, 0), (1, 0), (-1, 1)],
-        vec![(1, -1), (-1, 1)],
-        vec![(0, -1), (0, 0), (1, 0), (-1, 1)],
-        vec![(1, -1), (-1, 1)],
-    ];
-
-    static ref SPAWNS: Vec<(i32, i32, i32)> = vec![(3, 1, 0), (4, 0, 0), (3, 0, 0), (3, 0, 0), (3, 0, 0), (3, 0, 0), (3, 0, 0)];
-
-    static ref ZOBRISTS: Vec<Vec<u64>> = {
-        let mut rng = SmallRng::seed_from_u64(0xDEADBEEF12345678);
-
-        let mut board = Vec::new();
-        for _ in  0..BOARD_HEIGHT {
-            let mut row = Vec::new();
-            for _ in 0..BOARD_WIDTH {
-                row.push(rng.gen::<u64>());
-            }
-            board.push(row);
+/// Random keys of the cells for board hashes, from a fixed SplitMix64
+/// stream so that hashes are the same in every run.
+const ZOBRISTS: [[u64; MAX_WIDTH]; MAX_HEIGHT] = {
+    let mut table = [[0; MAX_WIDTH]; MAX_HEIGHT];
+    let mut state: u64 = 0xDEADBEEF12345678;
+
+    let mut y = 0;
+    while y < MAX_HEIGHT {
+        let mut x = 0;
+        while x < MAX_WIDTH {
+            state = state.wrapping_add(0x9E3779B97F4A7C15);
+            let mut z = state;
+            z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+            z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+            table[y][x] = z ^ (z >> 31);
+            x += 1;
         }
+        y += 1;
+    }
 
-        board
-    };
-}
+    table
+};
 
 pub trait Cell {
     fn is_empty(&self) -> bool;
 }
 
-#[derive(Debug, Clone, Copy, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(u8)]
 pub enum Color {
     Empty,
@@ -210,6 +83,7 @@ pub enum Color {
     T,
     Z,
     Random,
+    Garbage,
 }
 
 impl From<u8> for Color {
@@ -228,6 +102,23 @@ impl From<u8> for Color {
     }
 }
 
+impl Color {
+    /// The piece this cell colour stands for, if any.
+    #[inline]
+    pub const fn piece(self) -> Option<PieceKind> {
+        match self {
+            Color::I => Some(PieceKind::I),
+            Color::O => Some(PieceKind::O),
+            Color::J => Some(PieceKind::J),
+            Color::L => Some(PieceKind::L),
+            Color::S => Some(PieceKind::S),
+            Color::T => Some(PieceKind::T),
+            Color::Z => Some(PieceKind::Z),
+            Color::Empty | Color::Random | Color::Garbage => None,
+        }
+    }
+}
+
 impl Cell for Color {
     #[inline]
     fn is_empty(&self) -> bool {
@@ -235,16 +126,286 @@ impl Cell for Color {
     }
 }
 
-#[repr(u8)]
-#[derive(Clone, Copy, PartialEq, Debug)]
-pub enum Mask {
-    Unset,
-    Set,
+/// Playfield of `width` by `height` cells, row 0 at the top. Boards of
+/// every size share the same storage so that they stay cheap to copy.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Board<T> {
+    width: usize,
+    height: usize,
+    cells: [[T; MAX_WIDTH]; MAX_HEIGHT],
+}
+
+impl<T: Copy> Board<T> {
+    /// A board with every cell set to `cell`. Panics if the size is not
+    /// valid.
+    pub fn new(width: usize, height: usize, cell: T) -> Self {
+        assert!(Self::is_valid_size(width, height));
+
+        Board {
+            width,
+            height,
+            cells: [[cell; MAX_WIDTH]; MAX_HEIGHT],
+        }
+    }
+}
+
+impl<T> Board<T> {
+    pub fn is_valid_size(width: usize, height: usize) -> bool {
+        (MIN_SIZE..=MAX_WIDTH).contains(&width) && (MIN_SIZE..=MAX_HEIGHT).contains(&height)
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn rows(&self) -> impl Iterator<Item = &[T]> {
+        self.cells[..self.height]
+            .iter()
+            .map(|row| &row[..self.width])
+    }
+}
+
+impl<T: Copy> Board<T> {
+    /// Moves every row up by `count`, dropping the top ones, and fills the
+    /// bottom with copies of `row`.
+    fn push_rows(&mut self, count: usize, row: [T; MAX_WIDTH]) {
+        let count = count.min(self.height);
+        self.cells.copy_within(count..self.height, 0);
+        self.cells[self.height - count..self.height].fill(row);
+    }
+
+    /// Removes the rows set in the `rows` bitset, moving the rows above them
+    /// down and filling the top with `empty`.
+    fn remove_rows(&mut self, rows: u64, empty: T) {
+        let mut to = self.height;
+        for from in (0..self.height).rev() {
+            if rows & (1 << from) == 0 {
+                to -= 1;
+                self.cells[to] = self.cells[from];
+            }
+        }
+
+        for row in self.cells[..to].iter_mut() {
+            *row = [empty; MAX_WIDTH];
+        }
+    }
+}
+
+/// Occupancy of a board as one bitmask per row, bit `x` standing for
+/// column `x`, for fast collision, lock and line clear tests.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BitBoard {
+    width: usize,
+    height: usize,
+    rows: [u16; MAX_HEIGHT],
 }
 
-impl Cell for Mask {
+impl BitBoard {
+    pub fn new<T: Cell>(board: &Board<T>) -> Self {
+        let mut rows = [0; MAX_HEIGHT];
+        for (y, row) in board.rows().enumerate() {
+            for (x, cell) in row.iter().enumerate() {
+                if !cell.is_empty() {
+                    rows[y] |= 1 << x;
+                }
+            }
+        }
+
+        BitBoard {
+            width: board.width(),
+            height: board.height(),
+            rows,
+        }
+    }
+
+    #[inline]
+    pub fn get(&self, x: usize, y: usize) -> bool {
+        self.rows[y] & (1 << x) != 0
+    }
+
+    fn full_row(&self) -> u16 {
+        ((1u32 << self.width) - 1) as u16
+    }
+
+    /// Cells at or below the top filled cell of their column.
+    fn below_stack(&self) -> BitBoard {
+        let mut below = *self;
+        let mut seen = 0;
+        for row in below.rows[..self.height].iter_mut() {
+            seen |= *row;
+            *row = seen;
+        }
+
+        below
+    }
+
+    /// Whether `piece` at `(x, y)` leaves the board or overlaps a cell.
+    #[inline]
+    fn collides(&self, piece: &Shape, x: i32, y: i32) -> bool {
+        if x < 0
+            || x > (self.width - piece.width) as i32
+            || y < 0
+            || y > (self.height - piece.height) as i32
+        {
+            return true;
+        }
+
+        let (x, y) = (x as usize, y as usize);
+        piece.rows[..piece.height]
+            .iter()
+            .zip(&self.rows[y..])
+            .any(|(&piece_row, &row)| row & (piece_row << x) != 0)
+    }
+
+    fn place(&mut self, piece: &Shape, x: usize, y: usize) {
+        for (j, &piece_row) in piece.rows[..piece.height].iter().enumerate() {
+            self.rows[y + j] |= piece_row << x;
+        }
+    }
+
+    /// Bitset of the full rows.
+    fn full_rows(&self) -> u64 {
+        let full = self.full_row();
+        (0..self.height)
+            .filter(|&y| self.rows[y] == full)
+            .fold(0, |rows, y| rows | 1 << y)
+    }
+
+    /// Removes the rows set in `rows`, moving the rows above them down.
+    fn remove_rows(&mut self, rows: u64) {
+        let mut to = self.height;
+        for from in (0..self.height).rev() {
+            if rows & (1 << from) == 0 {
+                to -= 1;
+                self.rows[to] = self.rows[from];
+            }
+        }
+
+        self.rows[..to].fill(0);
+    }
+
+    /// Moves every row up by `count` and fills the bottom with `row`,
+    /// returning whether cells were pushed off the top.
+    fn push_rows(&mut self, count: usize, row: u16) -> bool {
+        let count = count.min(self.height);
+        let pushed_out = self.rows[..count].iter().any(|&row| row != 0);
+        self.rows.copy_within(count..self.height, 0);
+        self.rows[self.height - count..self.height].fill(row);
+
+        pushed_out
+    }
+
     fn is_empty(&self) -> bool {
-        *self == Mask::Unset
+        self.rows[..self.height].iter().all(|&row| row == 0)
+    }
+
+    /// Rows above the visible field, with the pieces spawning in the last
+    /// `HIDDEN_ROWS` of them.
+    fn hidden_rows(&self) -> usize {
+        hidden_rows(self.height)
+    }
+
+    /// Height of the stack in each column, from the floor to its top filled
+    /// cell.
+    fn heights(&self) -> [usize; MAX_WIDTH] {
+        let mut heights = [0; MAX_WIDTH];
+        let mut seen = 0;
+        for (y, &row) in self.rows[..self.height].iter().enumerate() {
+            let mut new = row & !seen;
+            while new != 0 {
+                heights[new.trailing_zeros() as usize] = self.height - y;
+                new &= new - 1;
+            }
+            seen |= row;
+        }
+
+        heights
+    }
+
+    /// Height of column `x` when only the cells up to `height` count.
+    fn top_below(&self, x: usize, mut height: usize) -> usize {
+        while height > 0 && !self.get(x, self.height - height) {
+            height -= 1;
+        }
+
+        height
+    }
+
+    /// Occupancy by column, bit `y` standing for row `y`.
+    fn columns(&self) -> [u64; MAX_WIDTH] {
+        let mut columns = [0; MAX_WIDTH];
+        for (y, &row) in self.rows[..self.height].iter().enumerate() {
+            let mut row = row;
+            while row != 0 {
+                columns[row.trailing_zeros() as usize] |= 1 << y;
+                row &= row - 1;
+            }
+        }
+
+        columns
+    }
+}
+
+/// Board masks and search state shared by the pieces of a `legal_moves`
+/// call.
+struct MoveScratch {
+    /// Cells at or below the top of their column.
+    open_air_mask: BitBoard,
+    columns: [u64; MAX_WIDTH],
+    open_air_columns: [u64; MAX_WIDTH],
+    cache: StateSet,
+    frontier_cache: BinaryHeap<OrderedMove>,
+}
+
+/// Set of states on the board, as a bitboard per rotation.
+#[derive(Debug)]
+struct StateSet {
+    rows: [[u16; MAX_HEIGHT]; 4],
+}
+
+impl Default for StateSet {
+    fn default() -> Self {
+        StateSet {
+            rows: [[0; MAX_HEIGHT]; 4],
+        }
+    }
+}
+
+impl StateSet {
+    fn contains(&self, (x, y, rot): State) -> bool {
+        self.rows[rot as usize][y as usize] & (1 << x) != 0
+    }
+
+    /// Adds `state`, returning whether it was missing.
+    fn insert(&mut self, state: State) -> bool {
+        let (x, y, rot) = state;
+        let missing = !self.contains(state);
+        self.rows[rot as usize][y as usize] |= 1 << x;
+        missing
+    }
+
+    fn clear(&mut self) {
+        *self = StateSet::default();
+    }
+}
+
+impl<T> Index<usize> for Board<T> {
+    type Output = [T];
+
+    #[inline]
+    fn index(&self, y: usize) -> &[T] {
+        &self.cells[..self.height][y][..self.width]
+    }
+}
+
+impl<T> IndexMut<usize> for Board<T> {
+    #[inline]
+    fn index_mut(&mut self, y: usize) -> &mut [T] {
+        &mut self.cells[..self.height][y][..self.width]
     }
 }
 
@@ -262,6 +423,7 @@ impl TryFrom<char> for Color {
             'T' => Ok(Color::T),
             'Z' => Ok(Color::Z),
             '?' => Ok(Color::Random),
+            'G' => Ok(Color::Garbage),
             _ => Err(()),
         }
     }
@@ -281,6 +443,7 @@ impl fmt::Display for Color {
                 Color::T => 'T',
                 Color::Z => 'Z',
                 Color::Random => '?',
+                Color::Garbage => 'G',
                 _ => ' ',
             }
         )?;
@@ -289,6 +452,9 @@ impl fmt::Display for Color {
     }
 }
 
+/// Names of the network inputs, in the order produced by [`Features::inputs`].
+pub const FEATURE_NAMES: [&str; 3] = ["holes", "bumpiness", "aggregate_height"];
+
 #[derive(Debug)]
 pub struct Features {
     pub holes: f64,
@@ -296,24 +462,139 @@ pub struct Features {
     pub aggregate_height: f64,
 }
 
+impl Features {
+    pub fn inputs(&self) -> Vec<f64> {
+        vec![self.holes, self.bumpiness, self.aggregate_height]
+    }
+}
+
+/// Game rules that move generation and `apply_move` need but that are not
+/// part of the position.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rules {
+    pub line_scores: [i64; 4],
+    pub scoring: ScoringSystem,
+    pub hold: bool,
+    pub rotation: RotationSystem,
+    /// Let paths end with a hard drop from as high up as possible.
+    pub prefer_hard_drop: bool,
+    /// Find paths with the fewest inputs.
+    pub finesse: bool,
+    /// Allow `DasLeft` and `DasRight` in finesse paths.
+    pub das: bool,
+    /// Only keep placements whose path can be played in time at these
+    /// timings.
+    pub timing: Option<Timing>,
+    pub levels: LevelSystem,
+    pub start_level: usize,
+    /// Replace the gravity of `timing` with the one of the current level.
+    pub level_gravity: bool,
+    /// Also end the game once blocks reach the top row, as happens when
+    /// garbage pushes the stack up.
+    pub top_out: bool,
+    /// Line clears send garbage, cancelling the pending garbage first.
+    pub garbage: bool,
+}
+
+impl Rules {
+    /// Timings a position at `level` is played with, if any.
+    pub fn timing_at(&self, level: usize) -> Option<Timing> {
+        self.timing.map(|timing| {
+            if self.level_gravity {
+                Timing {
+                    gravity: self.levels.gravity(level),
+                    ..timing
+                }
+            } else {
+                timing
+            }
+        })
+    }
+}
+
+impl Default for Rules {
+    fn default() -> Self {
+        Rules {
+            line_scores: [40, 100, 300, 1200],
+            scoring: ScoringSystem::Classic,
+            hold: false,
+            rotation: RotationSystem::Classic,
+            prefer_hard_drop: false,
+            finesse: false,
+            das: false,
+            timing: None,
+            levels: LevelSystem::Nes,
+            start_level: 0,
+            level_gravity: false,
+            top_out: false,
+            garbage: false,
+        }
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Hash, Serialize)]
 pub enum Action {
     MoveLeft,
     MoveRight,
     SoftDrop,
+    HardDrop,
+    /// Move to the wall, or until blocked, with a single held input.
+    DasLeft,
+    DasRight,
     RotateCounterclockwise,
     RotateClockwise,
+    Hold,
     None,
 }
 
+/// Why a game ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum GameOver {
+    /// The spawn of the current piece overlaps the stack.
+    BlockOut,
+    /// The last piece locked entirely above the visible field.
+    LockOut,
+    /// Blocks reached the top row.
+    TopOut,
+    /// No placement of the current piece is playable.
+    NoMoves,
+}
+
+/// Where a piece locks, and which piece went to the hold slot to free it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Placement {
+    pub piece: PieceKind,
+    pub x: usize,
+    pub y: usize,
+    pub rot: usize,
+    pub held: Option<PieceKind>,
+}
+
+impl Placement {
+    pub fn new(piece: PieceKind, x: usize, y: usize, rot: usize) -> Placement {
+        Placement {
+            piece,
+            x,
+            y,
+            rot,
+            held: None,
+        }
+    }
+
+    /// The piece that was current when this placement was chosen.
+    pub fn dealt(&self) -> PieceKind {
+        self.held.unwrap_or(self.piece)
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Hash)]
 struct Move {
     action: Action,
-    dest: (i32, i32, i32),
+    dest: State,
 }
 
 impl Move {
-    fn new(action: Action, dest: (i32, i32, i32)) -> Move {
+    fn new(action: Action, dest: State) -> Move {
         Move { action, dest }
     }
 }
@@ -342,44 +623,161 @@ impl PartialOrd for OrderedMove {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Position {
     pub score: i64,
-    pub last_piece: Color,
+    pub lines: usize,
+    pub level: usize,
+    /// Line clears in a row, ending with the last placement.
+    pub combo: usize,
+    /// The last line clear was a tetris or a T-spin.
+    pub back_to_back: bool,
+    /// The last piece locked entirely in the hidden rows.
+    pub lock_out: bool,
+    /// Rising garbage pushed blocks off the top of the board.
+    pub garbage_out: bool,
+    /// Incoming garbage, which rises once a piece locks without clearing.
+    pub pending_garbage: GarbageQueue,
+    /// Lines sent to the opponent, net of the ones that cancelled garbage.
+    pub garbage_sent: usize,
     pub current_piece: Color,
-    pub next_piece: Color,
-    pub board: Board<Color>,
+    /// Preview pieces, known ones first, then `Color::Random` once the
+    /// search runs past what the game showed.
+    pub queue: Queue,
+    pub hold_piece: Color,
+    pub hold_used: bool,
+    pub randomizer: Randomizer,
+    /// Colours of the cells, for display. Positions made for the search
+    /// leave them out, as `bits` is all it needs.
+    board: Option<Box<Board<Color>>>,
+    pub bits: BitBoard,
     pub hash: u64,
-    pub min_y: usize,
+    /// Stack height of each column, kept in step with `bits`.
+    heights: [usize; MAX_WIDTH],
 }
 
 impl Position {
     pub fn new(
-        last_piece: Color,
         current_piece: Color,
-        next_piece: Color,
+        queue: Queue,
         score: i64,
         board: Board<Color>,
         hash: u64,
-        min_y: usize,
     ) -> Self {
+        let bits = BitBoard::new(&board);
         Position {
-            last_piece,
+            randomizer: dealt(RandomizerKind::Nes, current_piece, &queue),
             current_piece,
-            next_piece,
+            queue,
+            hold_piece: Color::Empty,
+            hold_used: false,
             score,
-            board,
+            lines: 0,
+            level: 0,
+            combo: 0,
+            back_to_back: false,
+            lock_out: false,
+            garbage_out: false,
+            pending_garbage: GarbageQueue::new(),
+            garbage_sent: 0,
+            heights: bits.heights(),
+            bits,
+            board: Some(Box::new(board)),
             hash,
-            min_y,
         }
     }
 
-    pub fn path(&self, goal: (i32, i32, i32)) -> Vec<Action> {
-        let rot_num = ROTATION_OFFSETS[self.current_piece as usize - 1].len() as i32;
+    /// Colours of the cells. Panics on a position made for the search.
+    pub fn board(&self) -> &Board<Color> {
+        self.board
+            .as_deref()
+            .expect("search positions have no colours")
+    }
+
+    /// A copy without the colours, whose moves only update `bits`.
+    pub fn uncolored(&self) -> Position {
+        Position {
+            score: self.score,
+            lines: self.lines,
+            level: self.level,
+            combo: self.combo,
+            back_to_back: self.back_to_back,
+            lock_out: self.lock_out,
+            garbage_out: self.garbage_out,
+            pending_garbage: self.pending_garbage.clone(),
+            garbage_sent: self.garbage_sent,
+            current_piece: self.current_piece,
+            queue: self.queue.clone(),
+            hold_piece: self.hold_piece,
+            hold_used: self.hold_used,
+            randomizer: self.randomizer,
+            board: None,
+            bits: self.bits,
+            hash: self.hash,
+            heights: self.heights,
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.bits.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.bits.height
+    }
+
+    /// Stack height of each column, from the floor to its top filled cell.
+    pub fn heights(&self) -> &[usize] {
+        &self.heights[..self.bits.width]
+    }
+
+    /// Height of the tallest column.
+    pub fn stack_height(&self) -> usize {
+        self.heights().iter().copied().max().unwrap_or(0)
+    }
+
+    /// Switches to another randomizer, keeping the state if it is already
+    /// of that kind.
+    pub fn set_randomizer(&mut self, kind: RandomizerKind) {
+        if self.randomizer.kind() != kind {
+            self.randomizer = dealt(kind, self.current_piece, &self.queue);
+        }
+    }
+
+    /// Queues incoming garbage, its hole being kept on the board.
+    pub fn receive_garbage(&mut self, garbage: Garbage) {
+        let hole = garbage.hole.min(self.bits.width - 1);
+        garbage::receive(
+            &mut self.pending_garbage,
+            Garbage { hole, ..garbage },
+            self.bits.height,
+        );
+    }
+
+    pub fn next_piece(&self) -> Color {
+        self.queue.first().copied().unwrap_or(Color::Random)
+    }
+
+    /// Deals or forgets preview pieces until `len` of them are shown.
+    pub fn set_preview<R: Rng + ?Sized>(&mut self, len: usize, rng: &mut R) {
+        let len = len.min(MAX_PREVIEW);
+
+        if self.queue.len() > len {
+            self.queue.truncate(len);
+            self.randomizer = dealt(self.randomizer.kind(), self.current_piece, &self.queue);
+        }
+
+        while self.queue.len() < len {
+            self.queue.push(self.randomizer.deal(rng));
+        }
+    }
+
+    pub fn path(&self, rules: &Rules, kind: PieceKind, goal: State) -> Vec<Action> {
+        let rot_num = rules.rotation.rotations(kind);
 
         let mut goal_mv = None;
 
-        let start = SPAWNS[self.current_piece as usize - 1];
+        let start = self.spawn(rules, kind);
         let start_move = OrderedMove::new(Move::new(Action::None, start), 0);
 
         let mut frontier = BinaryHeap::new();
@@ -399,68 +797,52 @@ impl Position {
                 break;
             }
 
-            let mut piece = &PIECES[self.current_piece as usize - 1]
-                [wrap_rot(current.dest.2, rot_num) as usize];
+            let piece = kind.shape(dest.2);
 
-            let mut move_list: ArrayVec<Move, 5> = ArrayVec::new();
+            let mut move_list: ArrayVec<Move, 8> = ArrayVec::new();
 
-            if !check_collision(&self.board, piece, dest.0 - 1, dest.1) {
+            if !self.bits.collides(piece, dest.0 - 1, dest.1) {
                 move_list.push(Move::new(Action::MoveLeft, (dest.0 - 1, dest.1, dest.2)));
             }
 
-            if !check_collision(&self.board, piece, dest.0 + 1, dest.1) {
+            if !self.bits.collides(piece, dest.0 + 1, dest.1) {
                 move_list.push(Move::new(Action::MoveRight, (dest.0 + 1, dest.1, dest.2)));
             }
 
-            if !check_collision(&self.board, piece, dest.0, dest.1 + 1) {
+            if !self.bits.collides(piece, dest.0, dest.1 + 1) {
                 move_list.push(Move::new(Action::SoftDrop, (dest.0, dest.1 + 1, dest.2)));
             }
 
-            let mut rot = wrap_rot(dest.2 - 1, rot_num) as usize;
-            let mut rot_offset = ROTATION_OFFSETS[self.current_piece as usize - 1][rot];
-
-            piece = &PIECES[self.current_piece as usize - 1][rot];
-
-            if !check_collision(
-                &self.board,
-                piece,
-                dest.0 - rot_offset.0,
-                dest.1 - rot_offset.1,
-            ) {
-                move_list.push(Move::new(
-                    Action::RotateCounterclockwise,
-                    (
-                        dest.0 - rot_offset.0,
-                        dest.1 - rot_offset.1,
-                        wrap_rot(dest.2 - 1, rot_num),
-                    ),
-                ));
+            if let Some(next) = self.rotate(rules, kind, dest, -1) {
+                move_list.push(Move::new(Action::RotateCounterclockwise, next));
+            }
+
+            if let Some(next) = self.rotate(rules, kind, dest, 1) {
+                move_list.push(Move::new(Action::RotateClockwise, next));
+            }
+
+            if rules.finesse && rules.das {
+                for (action, dx) in [(Action::DasLeft, -1), (Action::DasRight, 1)] {
+                    let next = self.slide(kind, dest, (dx, 0));
+                    if next != dest {
+                        move_list.push(Move::new(action, next));
+                    }
+                }
             }
 
-            rot = wrap_rot(dest.2 + 1, rot_num) as usize;
-            rot_offset = ROTATION_OFFSETS[self.current_piece as usize - 1][dest.2 as usize];
-            piece = &PIECES[self.current_piece as usize - 1][rot];
-
-            if !check_collision(
-                &self.board,
-                piece,
-                dest.0 + rot_offset.0,
-                dest.1 + rot_offset.1,
-            ) {
-                move_list.push(Move::new(
-                    Action::RotateClockwise,
-                    (
-                        dest.0 + rot_offset.0,
-                        dest.1 + rot_offset.1,
-                        wrap_rot(dest.2 + 1, rot_num),
-                    ),
-                ));
+            if (rules.prefer_hard_drop || rules.finesse)
+                && dest.1 < goal.1
+                && self.slide(kind, dest, (0, 1)) == goal
+            {
+                move_list.push(Move::new(Action::HardDrop, goal));
             }
 
             for &next in move_list.iter() {
-                // Lower costs to higher actions
+                // Every input counts the same in finesse mode, otherwise
+                // lower costs to higher actions
                 let c = match next.action {
-                    Action::SoftDrop => 1,
+                    _ if rules.finesse => 1,
+                    Action::SoftDrop | Action::HardDrop => 1,
                     _ => next.dest.1 + 1,
                 };
 
@@ -469,7 +851,13 @@ impl Position {
                     || new_cost < *cost_so_far.get(&next.dest).unwrap()
                 {
                     cost_so_far.insert(next.dest, new_cost);
-                    let priority = new_cost + proximity(goal, next.dest, rot_num);
+                    // DAS breaks the distance heuristic, so finesse paths
+                    // come from a plain Dijkstra search
+                    let priority = if rules.finesse {
+                        new_cost
+                    } else {
+                        new_cost + proximity(goal, next.dest, rot_num)
+                    };
                     frontier.push(OrderedMove::new(next, priority));
                     came_from.insert(next.dest, Some(current));
                 }
@@ -487,7 +875,7 @@ impl Position {
             match came_from.get(&current) {
                 Some(&m) => mv = m,
                 None => {
-                    eprintln!("Move {:?} [{}] on {}", goal, self.min_y, self);
+                    eprintln!("Move {:?} [{}] on {}", goal, self.stack_height(), self);
                     // eprintln!("{:?}", comm::POSITION_HISTORY.lock().unwrap());
                     panic!()
                 }
@@ -498,18 +886,59 @@ impl Position {
 
         path.reverse();
 
+        // The last straight descent locks the piece with a single hard drop
+        if path.last() == Some(&Action::SoftDrop) {
+            while path.last() == Some(&Action::SoftDrop) {
+                path.pop();
+            }
+            path.push(Action::HardDrop);
+        }
+
         path
     }
 
+    /// Where `kind` spawns, the spawns of the rotation system being
+    /// moved to the middle of the board and down to the visible field.
+    pub fn spawn(&self, rules: &Rules, kind: PieceKind) -> State {
+        let (x, y, rot) = rules.rotation.spawn(kind);
+        let dx = (self.bits.width as i32 - BOARD_WIDTH as i32) / 2;
+        let dy = (self.bits.hidden_rows() - HIDDEN_ROWS) as i32;
+
+        (x + dx, y + dy, rot)
+    }
+
+    /// Whether the piece fits on the board at `state`.
+    pub fn fits(&self, kind: PieceKind, (x, y, rot): State) -> bool {
+        !self.bits.collides(kind.shape(rot), x, y)
+    }
+
+    /// Where the piece at `state` stops when pushed by `(dx, dy)` until it
+    /// hits something, as with a hard drop or a DAS move.
+    pub fn slide(
+        &self,
+        kind: PieceKind,
+        (mut x, mut y, rot): State,
+        (dx, dy): (i32, i32),
+    ) -> State {
+        let piece = kind.shape(rot);
+        while !self.bits.collides(piece, x + dx, y + dy) {
+            x += dx;
+            y += dy;
+        }
+
+        (x, y, rot)
+    }
+
     fn path_reverse(
         &self,
-        piece: Color,
-        start: (i32, i32, i32),
+        rules: &Rules,
+        kind: PieceKind,
+        start: State,
         frontier: &mut BinaryHeap<OrderedMove>,
     ) -> bool {
-        let rot_num = ROTATION_OFFSETS[piece as usize - 1].len() as i32;
+        let rot_num = rules.rotation.rotations(kind);
 
-        let goal = SPAWNS[piece as usize - 1];
+        let goal = self.spawn(rules, kind);
         let start_move = OrderedMove::new(Move::new(Action::None, start), 0);
 
         frontier.push(start_move);
@@ -527,62 +956,28 @@ impl Position {
                 return true;
             }
 
-            let mut piece = &PIECES[self.current_piece as usize - 1]
-                [wrap_rot(current.dest.2, rot_num) as usize];
+            let piece = kind.shape(dest.2);
 
-            let mut move_list: ArrayVec<Move, 5> = ArrayVec::new();
+            let mut move_list: ArrayVec<Move, 13> = ArrayVec::new();
 
-            if !check_collision(&self.board, piece, dest.0 - 1, dest.1) {
+            if !self.bits.collides(piece, dest.0 - 1, dest.1) {
                 move_list.push(Move::new(Action::MoveRight, (dest.0 - 1, dest.1, dest.2)));
             }
 
-            if !check_collision(&self.board, piece, dest.0 + 1, dest.1) {
+            if !self.bits.collides(piece, dest.0 + 1, dest.1) {
                 move_list.push(Move::new(Action::MoveLeft, (dest.0 + 1, dest.1, dest.2)));
             }
 
-            if !check_collision(&self.board, piece, dest.0, dest.1 - 1) {
+            if !self.bits.collides(piece, dest.0, dest.1 - 1) {
                 move_list.push(Move::new(Action::SoftDrop, (dest.0, dest.1 - 1, dest.2)));
             }
 
-            let mut rot = wrap_rot(dest.2 - 1, rot_num) as usize;
-            let mut rot_offset = ROTATION_OFFSETS[self.current_piece as usize - 1][rot];
-
-            piece = &PIECES[self.current_piece as usize - 1][rot];
-
-            if !check_collision(
-                &self.board,
-                piece,
-                dest.0 - rot_offset.0,
-                dest.1 - rot_offset.1,
-            ) {
-                move_list.push(Move::new(
-                    Action::RotateClockwise,
-                    (
-                        dest.0 - rot_offset.0,
-                        dest.1 - rot_offset.1,
-                        wrap_rot(dest.2 - 1, rot_num),
-                    ),
-                ));
+            for prev in self.unrotate(rules, kind, dest, 1) {
+                move_list.push(Move::new(Action::RotateClockwise, prev));
             }
 
-            rot = wrap_rot(dest.2 + 1, rot_num) as usize;
-            rot_offset = ROTATION_OFFSETS[self.current_piece as usize - 1][dest.2 as usize];
-            piece = &PIECES[self.current_piece as usize - 1][rot];
-
-            if !check_collision(
-                &self.board,
-                piece,
-                dest.0 + rot_offset.0,
-                dest.1 + rot_offset.1,
-            ) {
-                move_list.push(Move::new(
-                    Action::RotateCounterclockwise,
-                    (
-                        dest.0 + rot_offset.0,
-                        dest.1 + rot_offset.1,
-                        wrap_rot(dest.2 + 1, rot_num),
-                    ),
-                ));
+            for prev in self.unrotate(rules, kind, dest, -1) {
+                move_list.push(Move::new(Action::RotateCounterclockwise, prev));
             }
 
             for &next in move_list.iter() {
@@ -609,492 +1004,1133 @@ impl Position {
 
     fn pathfind_open_air(
         &self,
-        open_air_mask: &Board<Mask>,
-        piece_idx: usize,
-        x: i32,
-        y: i32,
-        rot: i32,
-        cache: &mut FxHashSet<(i32, i32, i32)>,
+        rules: &Rules,
+        open_air_mask: &BitBoard,
+        kind: PieceKind,
+        state: State,
+        cache: &mut StateSet,
     ) -> bool {
-        if cache.contains(&(x, y, rot)) {
-            return false;
-        }
-
-        cache.insert((x, y, rot));
-
-        let piece = &PIECES[piece_idx][rot as usize];
+        let (x, y, rot) = state;
+        let piece = kind.shape(rot);
 
-        if check_collision(&self.board, piece, x, y) {
+        // Only states on the board get cached
+        if self.bits.collides(piece, x, y) || !cache.insert(state) {
             return false;
         }
 
-        if !check_collision(open_air_mask, piece, x, y) {
+        if !open_air_mask.collides(piece, x, y) {
             return true;
         }
 
-        if self.pathfind_open_air(open_air_mask, piece_idx, x + 1, y, rot, cache) {
-            return true;
+        for prev in [(x + 1, y, rot), (x - 1, y, rot), (x, y - 1, rot)] {
+            if self.pathfind_open_air(rules, open_air_mask, kind, prev, cache) {
+                return true;
+            }
         }
 
-        if self.pathfind_open_air(open_air_mask, piece_idx, x - 1, y, rot, cache) {
-            return true;
+        for dir in [1, -1] {
+            for prev in self.unrotate(rules, kind, state, dir) {
+                if self.pathfind_open_air(rules, open_air_mask, kind, prev, cache) {
+                    return true;
+                }
+            }
         }
 
-        if self.pathfind_open_air(open_air_mask, piece_idx, x, y - 1, rot, cache) {
-            return true;
-        }
+        false
+    }
+
+    /// State reached by rotating the piece at `state` clockwise (`dir == 1`)
+    /// or counterclockwise (`dir == -1`), taking the first kick that fits.
+    pub fn rotate(&self, rules: &Rules, kind: PieceKind, state: State, dir: i32) -> Option<State> {
+        rules
+            .rotation
+            .kicks(kind, state, dir)
+            .into_iter()
+            .find(|&(x, y, rot)| !self.bits.collides(kind.shape(rot), x, y))
+    }
 
-        let rot_num = ROTATION_OFFSETS[piece_idx].len() as i32;
-        let mut rot_offset = ROTATION_OFFSETS[piece_idx][wrap_rot(rot - 1, rot_num) as usize];
+    /// States from which rotating in direction `dir` ends at `state`.
+    fn unrotate(
+        &self,
+        rules: &Rules,
+        kind: PieceKind,
+        state: State,
+        dir: i32,
+    ) -> ArrayVec<State, 5> {
+        let from = wrap_rot(state.2 - dir, rules.rotation.rotations(kind));
+        let piece = kind.shape(from);
+
+        // Kicks only depend on the rotation, so each one leads back to a
+        // single candidate, valid if no earlier kick would have fit
+        rules
+            .rotation
+            .kicks(kind, (0, 0, from), dir)
+            .into_iter()
+            .map(|(dx, dy, _)| (state.0 - dx, state.1 - dy, from))
+            .filter(|&prev| {
+                !self.bits.collides(piece, prev.0, prev.1)
+                    && self.rotate(rules, kind, prev, dir) == Some(state)
+            })
+            .collect()
+    }
 
-        if self.pathfind_open_air(
+    /// Legal placements grouped by the piece dealt as current piece. With
+    /// hold enabled a group also holds the placements of the piece it swaps
+    /// with, unless that piece is still unknown.
+    pub fn legal_moves(&self, rules: &Rules) -> ArrayVec<Vec<Placement>, 7> {
+        let mut legal_moves = ArrayVec::new();
+        let open_air_mask = self.bits.below_stack();
+        let mut scratch = MoveScratch {
             open_air_mask,
-            piece_idx,
-            x - rot_offset.0,
-            y - rot_offset.1,
-            ((rot - 1) % rot_num + rot_num) % rot_num,
-            cache,
-        ) {
-            return true;
-        }
+            columns: self.bits.columns(),
+            open_air_columns: open_air_mask.columns(),
+            cache: StateSet::default(),
+            frontier_cache: BinaryHeap::new(),
+        };
 
-        rot_offset = ROTATION_OFFSETS[piece_idx][rot as usize];
+        // Weird but works for the time being
+        let piece_list: ArrayVec<PieceKind, 7> = if self.current_piece == Color::Random {
+            PieceKind::ALL
+                .into_iter()
+                .filter(|&piece| self.randomizer.probability(piece.into()) > 0.)
+                .collect()
+        } else {
+            self.current_piece.piece().into_iter().collect()
+        };
 
-        if self.pathfind_open_air(
-            open_air_mask,
-            piece_idx,
-            x + rot_offset.0,
-            y + rot_offset.1,
-            (rot + 1) % rot_num,
-            cache,
-        ) {
-            return true;
-        }
+        let swap_piece = match self.hold_piece {
+            Color::Empty => self.next_piece(),
+            hold_piece => hold_piece,
+        };
+        // An unknown piece can't be swapped in
+        let swap_piece = swap_piece.piece().filter(|_| rules.hold && !self.hold_used);
 
-        false
-    }
+        let mut swap_moves = None;
 
-    pub fn legal_moves(&self) -> ArrayVec<Vec<(Color, usize, usize, usize)>, 7> {
-        let mut legal_moves = ArrayVec::new();
-        let mut open_air_mask = [[Mask::Set; BOARD_WIDTH]; BOARD_HEIGHT];
-        let mut cache = FxHashSet::default();
-        let mut frontier_cache = BinaryHeap::new();
+        for kind in piece_list {
+            let mut piece_legal_moves = self.piece_moves(rules, kind, &mut scratch);
 
-        for x in 0..BOARD_WIDTH {
-            let mut y = 0;
+            if let Some(swap_piece) = swap_piece.filter(|&swap_piece| swap_piece != kind) {
+                let swap_moves = swap_moves
+                    .get_or_insert_with(|| self.piece_moves(rules, swap_piece, &mut scratch));
 
-            while y < BOARD_HEIGHT && self.board[y][x].is_empty() {
-                open_air_mask[y][x] = Mask::Unset;
-                y += 1;
+                piece_legal_moves.extend(swap_moves.iter().map(|&placement| Placement {
+                    held: Some(kind),
+                    ..placement
+                }));
             }
+
+            legal_moves.push(piece_legal_moves)
         }
 
-        // Weird but works for the time being
-        let piece_list = if self.current_piece == Color::Random {
-            ArrayVec::from(PIECE_ARRAY)
-        } else {
-            let mut vec = ArrayVec::new();
-            vec.push(self.current_piece);
-            vec
-        };
+        if let Some(timing) = rules.timing_at(self.level) {
+            for piece_legal_moves in legal_moves.iter_mut() {
+                piece_legal_moves.retain(|&placement| {
+                    timing.schedule_placement(self, rules, placement).is_some()
+                });
+            }
+        }
+
+        legal_moves
+    }
+
+    fn piece_moves(
+        &self,
+        rules: &Rules,
+        kind: PieceKind,
+        scratch: &mut MoveScratch,
+    ) -> Vec<Placement> {
+        let MoveScratch {
+            open_air_mask,
+            columns,
+            open_air_columns,
+            cache,
+            frontier_cache,
+        } = scratch;
+
+        let shapes = kind.shapes().len() as i32;
+        let mut piece_legal_moves = Vec::new();
+        let mut found = StateSet::default();
+
+        for rot in 0..rules.rotation.rotations(kind) {
+            let piece = kind.shape(rot);
+            let heights = (1u64 << (self.bits.height - piece.height + 1)) - 1;
+
+            for x in 0..(self.bits.width - piece.width + 1) {
+                let fits = !piece.overlaps(columns, x) & heights;
+                let locks = fits & !(fits >> 1);
+                let open_air = !piece.overlaps(open_air_columns, x) & heights;
+
+                // Going up from the floor, the first spot in open air where
+                // the piece does not lock is where it falls through
+                let falls = open_air & !locks;
+                let mut locks = match falls {
+                    0 => locks,
+                    _ => locks & !((2 << (63 - falls.leading_zeros())) - 1),
+                };
+
+                while locks != 0 {
+                    let y = 63 - locks.leading_zeros() as usize;
+                    locks &= !(1 << y);
+
+                    // States sharing a shape lock into the same cells
+                    let cells = (x as i32, y as i32, rot % shapes);
+                    if found.contains(cells) {
+                        continue;
+                    }
 
-        for piece_color in piece_list {
-            let piece_kind = &PIECES[piece_color as usize - 1];
-            let mut piece_legal_moves = Vec::new();
-
-            for (rot, piece) in piece_kind.iter().enumerate() {
-                let size_x = piece[0].len();
-                let size_y = piece.len();
-                for x in 0..(BOARD_WIDTH - size_x + 1) {
-                    for y in (0..(BOARD_HEIGHT - size_y + 1)).rev() {
-                        let is_open_air =
-                            !check_collision(&open_air_mask, piece, x as i32, y as i32);
-                        if is_lock_fast(&self.board, piece, x, y, size_x, size_y) {
-                            if self.min_y < 4 {
-                                if self.path_reverse(
-                                    piece_color,
-                                    (x as i32, y as i32, rot as i32),
-                                    &mut frontier_cache,
-                                ) {
-                                    piece_legal_moves.push((piece_color, x, y, rot));
-                                }
-                                frontier_cache.clear();
-                            } else if !is_open_air {
-                                if self.pathfind_open_air(
-                                    &open_air_mask,
-                                    piece_color as usize - 1,
-                                    x as i32,
-                                    y as i32,
-                                    rot as i32,
-                                    &mut cache,
-                                ) {
-                                    piece_legal_moves.push((piece_color, x, y, rot));
-                                }
-
-                                cache.clear();
-                            } else {
-                                piece_legal_moves.push((piece_color, x, y, rot));
-                            }
-                        } else if is_open_air {
-                            break;
-                        }
+                    let state = (x as i32, y as i32, rot);
+                    // Near the spawn rows pieces may need to go up
+                    let reachable =
+                        if self.stack_height() + self.bits.hidden_rows() + 2 > self.bits.height {
+                            let reachable = self.path_reverse(rules, kind, state, frontier_cache);
+                            frontier_cache.clear();
+                            reachable
+                        } else if open_air & (1 << y) == 0 {
+                            let reachable =
+                                self.pathfind_open_air(rules, open_air_mask, kind, state, cache);
+                            cache.clear();
+                            reachable
+                        } else {
+                            true
+                        };
+
+                    if reachable {
+                        found.insert(cells);
+                        piece_legal_moves.push(Placement::new(kind, x, y, rot as usize));
                     }
                 }
             }
-
-            legal_moves.push(piece_legal_moves)
         }
 
-        legal_moves
+        piece_legal_moves
     }
 
-    pub fn features(&self) -> Features {
-        let mut holes = 0;
-        let mut heights: [f64; BOARD_WIDTH] = [0.; BOARD_WIDTH];
+    /// Input sequence bringing the piece of `placement` from its spawn to its
+    /// resting place, starting with a hold if it came out of the hold slot.
+    pub fn placement_path(&self, rules: &Rules, placement: Placement) -> Vec<Action> {
+        let mut path = Vec::new();
+        if placement.held.is_some() {
+            path.push(Action::Hold);
+        }
 
-        for y in (1..BOARD_HEIGHT).rev() {
-            for x in 0..BOARD_WIDTH {
-                if !self.board[y][x].is_empty() {
-                    heights[x] = (BOARD_HEIGHT - y) as f64;
-                }
+        path.extend(self.path(
+            rules,
+            placement.piece,
+            (placement.x as i32, placement.y as i32, placement.rot as i32),
+        ));
 
-                if !self.board[y - 1][x].is_empty() && self.board[y][x].is_empty() {
-                    holes += 1;
+        path
+    }
 
-                    let mut l = 1;
+    /// Cells covered by the piece of `placement` at its spawn and then after
+    /// each input of `path`, so clients can draw the moves without knowing
+    /// the rotation system.
+    pub fn path_cells(
+        &self,
+        rules: &Rules,
+        placement: Placement,
+        path: &[Action],
+    ) -> Vec<[(i32, i32); 4]> {
+        let cells = |kind: PieceKind, (x, y, rot): State| {
+            kind.shape(rot)
+                .cells()
+                .map(|(dx, dy)| (x + dx as i32, y + dy as i32))
+        };
 
-                    while y + l < BOARD_HEIGHT && self.board[y + l][x].is_empty() {
-                        holes += 1;
-                        l += 1;
-                    }
+        let mut kind = placement.dealt();
+        let mut state = self.spawn(rules, kind);
+        let mut path_cells = vec![cells(kind, state)];
+        for &action in path {
+            let (x, y, rot) = state;
+            let moved = |state: State| {
+                if self.fits(kind, state) {
+                    state
+                } else {
+                    (x, y, rot)
                 }
-            }
+            };
+            state = match action {
+                Action::MoveLeft => moved((x - 1, y, rot)),
+                Action::MoveRight => moved((x + 1, y, rot)),
+                Action::SoftDrop => moved((x, y + 1, rot)),
+                Action::HardDrop => self.slide(kind, state, (0, 1)),
+                Action::DasLeft => self.slide(kind, state, (-1, 0)),
+                Action::DasRight => self.slide(kind, state, (1, 0)),
+                Action::RotateCounterclockwise => {
+                    self.rotate(rules, kind, state, -1).unwrap_or(state)
+                }
+                Action::RotateClockwise => self.rotate(rules, kind, state, 1).unwrap_or(state),
+                Action::Hold => {
+                    kind = placement.piece;
+                    self.spawn(rules, kind)
+                }
+                Action::None => state,
+            };
+            path_cells.push(cells(kind, state));
         }
 
+        path_cells
+    }
+
+    pub fn features(&self) -> Features {
+        let heights = self.heights();
+        let cells: u32 = self.bits.rows[..self.bits.height]
+            .iter()
+            .map(|row| row.count_ones())
+            .sum();
+
         let bumpiness = heights
             .windows(2)
-            .map(|window| (window[0] - window[1]).abs())
+            .map(|window| window[0].abs_diff(window[1]) as f64)
             .sum();
 
-        let aggregate_height = heights.iter().sum();
+        let aggregate_height = heights.iter().sum::<usize>() as f64;
 
         Features {
-            holes: holes as f64,
+            // Every empty cell under the top of its column is a hole
+            holes: aggregate_height - cells as f64,
             aggregate_height,
             bumpiness,
         }
     }
 
+    /// T-spin kind of `placement`, by the three corner rule. A T that could
+    /// still move sideways or up is taken to have arrived without a rotation.
+    pub fn spin(&self, placement: Placement) -> Spin {
+        if placement.piece != PieceKind::T {
+            return Spin::None;
+        }
+
+        let state = (placement.x as i32, placement.y as i32, placement.rot as i32);
+        let (x, y, rot) = state;
+        if [(-1, 0), (1, 0), (0, -1)]
+            .iter()
+            .any(|&(dx, dy)| self.fits(PieceKind::T, (x + dx, y + dy, rot)))
+        {
+            return Spin::None;
+        }
+
+        // Center of the T and the two corners it points at
+        let ((cx, cy), front) = match rot % 4 {
+            0 => ((x + 1, y), [(-1, 1), (1, 1)]),
+            1 => ((x + 1, y + 1), [(-1, -1), (-1, 1)]),
+            2 => ((x + 1, y + 1), [(-1, -1), (1, -1)]),
+            _ => ((x, y + 1), [(1, -1), (1, 1)]),
+        };
+
+        let filled = |(dx, dy): (i32, i32)| {
+            let (cx, cy) = (cx + dx, cy + dy);
+            cx < 0
+                || cx >= self.bits.width as i32
+                || cy < 0
+                || cy >= self.bits.height as i32
+                || self.bits.get(cx as usize, cy as usize)
+        };
+
+        let corners = [(-1, -1), (1, -1), (-1, 1), (1, 1)]
+            .into_iter()
+            .filter(|&corner| filled(corner))
+            .count();
+
+        if corners < 3 {
+            Spin::None
+        } else if front.into_iter().all(filled) {
+            Spin::Full
+        } else {
+            Spin::Mini
+        }
+    }
+
+    /// Why the game is over in this position under `rules`, if it is. A
+    /// position can still have no playable move without being over here,
+    /// which ends the game with `GameOver::NoMoves` once searched.
+    pub fn is_game_over(&self, rules: &Rules) -> Option<GameOver> {
+        if self.lock_out {
+            return Some(GameOver::LockOut);
+        }
+
+        if self.garbage_out || (rules.top_out && self.bits.rows[0] != 0) {
+            return Some(GameOver::TopOut);
+        }
+
+        if let Some(piece) = self.current_piece.piece() {
+            if !self.fits(piece, self.spawn(rules, piece)) {
+                return Some(GameOver::BlockOut);
+            }
+        }
+
+        None
+    }
+
     pub fn apply_move(
         &self,
-        piece_color: Color,
-        x: usize,
-        y: usize,
-        rot: usize,
-        gen_next: bool,
+        rules: &Rules,
+        placement: Placement,
+        mut rng: Option<&mut SmallRng>,
     ) -> Position {
-        let piece = &PIECES[piece_color as usize - 1][rot];
-        let size_x = piece[0].len();
-        let size_y = piece.len();
+        let Placement {
+            piece: kind,
+            x,
+            y,
+            rot,
+            held,
+        } = placement;
+        let piece = kind.shape(rot as i32);
+        let spin = self.spin(placement);
 
         let mut new_board = self.board.clone();
+        let mut new_bits = self.bits;
         let mut new_score = self.score;
         let mut new_hash = self.hash;
 
-        let mut new_min_y = self.min_y.min(y);
+        let mut new_heights = self.heights;
 
         // Place the piece
-        for i in 0..size_x {
-            for j in 0..size_y {
-                if new_board[y + j][x + i].is_empty() && !piece[j][i].is_empty() {
-                    let piece_type = piece[j][i];
-                    new_board[y + j][x + i] = piece_type;
+        new_bits.place(piece, x, y);
+        for (i, &piece_column) in piece.columns[..piece.width].iter().enumerate() {
+            let top = self.bits.height - y - piece_column.trailing_zeros() as usize;
+            new_heights[x + i] = new_heights[x + i].max(top);
+        }
+        for (j, &piece_row) in piece.rows[..piece.height].iter().enumerate() {
+            for i in 0..piece.width {
+                if piece_row & (1 << i) != 0 {
+                    if let Some(board) = new_board.as_deref_mut() {
+                        board[y + j][x + i] = kind.into();
+                    }
                     new_hash ^= ZOBRISTS[y + j][x + i];
                 }
             }
         }
 
         // Update lines
-        let mut line_count = 0;
-        for j in 0..BOARD_HEIGHT {
-            let full_line = new_board[j].iter().all(|&cell| !cell.is_empty());
-
-            if full_line {
-                let new_board_copy = new_board.clone();
-                line_count += 1;
-                for y in 0..j {
-                    for x in 0..BOARD_WIDTH {
-                        let piece_type = new_board_copy[y][x];
-                        let old_piece_type = new_board_copy[y + 1][x];
-
-                        if !old_piece_type.is_empty() {
-                            new_hash ^= ZOBRISTS[y + 1][x];
-                        }
-
-                        if !piece_type.is_empty() {
-                            new_hash ^= ZOBRISTS[y + 1][x];
-                        }
-
-                        new_board[y + 1][x] = piece_type;
-                    }
-                }
+        let full_rows = new_bits.full_rows();
+        let line_count = full_rows.count_ones() as usize;
+        if line_count > 0 {
+            new_bits.remove_rows(full_rows);
+            if let Some(board) = new_board.as_deref_mut() {
+                board.remove_rows(full_rows, Color::Empty);
+            }
+            new_hash = hash_board(&new_bits);
+
+            // Columns sink by the cleared rows under their top, then further
+            // down when their top cell was cleared
+            let board_height = self.bits.height;
+            for (x, height) in new_heights[..self.bits.width].iter_mut().enumerate() {
+                let sunk = *height - (full_rows >> (board_height - *height)).count_ones() as usize;
+                *height = new_bits.top_below(x, sunk);
+            }
+        }
 
-                // Clear the top line
-                // TODO: Test hashing
-                for x in 0..BOARD_WIDTH {
-                    if new_board[0][x] != Color::Empty {
-                        new_board[0][x] = Color::Empty;
-                        new_hash ^= ZOBRISTS[0][x];
+        let clear = Clear {
+            lines: line_count,
+            spin,
+            perfect: line_count > 0 && new_bits.is_empty(),
+        };
+        let combo = if line_count > 0 { self.combo + 1 } else { 0 };
+        new_score += rules.scoring.points(
+            &rules.line_scores,
+            &clear,
+            self.level,
+            combo,
+            self.back_to_back,
+        );
+
+        // Clearing nothing leaves the back-to-back chain alone
+        let back_to_back = if line_count > 0 {
+            clear.is_difficult()
+        } else {
+            self.back_to_back
+        };
+
+        // Attacks cancel pending garbage first, the rest of which rises when
+        // nothing was cleared
+        let mut pending_garbage = self.pending_garbage.clone();
+        let attack = if rules.garbage {
+            garbage::attack(&clear, combo, self.back_to_back)
+        } else {
+            0
+        };
+        let garbage_sent = self.garbage_sent + garbage::cancel(&mut pending_garbage, attack);
+
+        let mut garbage_out = false;
+        if line_count == 0 && !pending_garbage.is_empty() {
+            for Garbage { lines, hole } in pending_garbage.drain(..) {
+                let mut row = [Color::Garbage; MAX_WIDTH];
+                row[hole] = Color::Empty;
+
+                garbage_out |= new_bits.push_rows(lines, new_bits.full_row() & !(1 << hole));
+                if let Some(board) = new_board.as_deref_mut() {
+                    board.push_rows(lines, row);
+                }
+                for (x, height) in new_heights[..self.bits.width].iter_mut().enumerate() {
+                    if x != hole || *height > 0 {
+                        *height += lines;
+                    }
+                    // Cells pushed off the top take their height with them
+                    if *height > self.bits.height {
+                        *height = new_bits.top_below(x, self.bits.height);
                     }
                 }
             }
+            new_hash = hash_board(&new_bits);
         }
 
-        new_score += match line_count {
-            1 => 40,
-            2 => 100,
-            3 => 300,
-            4 => 1200,
-            _ => 0,
+        // Levels follow the transitions of the rules from the current one
+        let new_lines = self.lines + line_count;
+
+        let mut randomizer = self.randomizer;
+        if self.current_piece == Color::Random {
+            randomizer.push(placement.dealt().into());
+        }
+
+        // Holding into an empty slot also consumes the next piece
+        let (hold_piece, consumed) = match held {
+            Some(held) if self.hold_piece == Color::Empty => (held.into(), 2),
+            Some(held) => (held.into(), 1),
+            None => (self.hold_piece, 1),
         };
 
-        new_min_y += line_count;
+        let mut upcoming: ArrayVec<Color, { MAX_PREVIEW + 2 }> =
+            self.queue.iter().copied().collect();
+        while upcoming.len() < self.queue.len() + consumed {
+            upcoming.push(match rng.as_deref_mut() {
+                Some(rng) => randomizer.deal(rng),
+                None => Color::Random,
+            });
+        }
+
+        debug_assert_eq!(new_heights, new_bits.heights());
 
-        return Position::new(
-            piece_color,
-            self.next_piece,
-            if gen_next {
-                self.sample(&mut rand::thread_rng())
-            } else {
-                Color::Random
-            },
-            new_score,
-            new_board,
-            new_hash,
-            new_min_y,
-        );
+        Position {
+            current_piece: upcoming[consumed - 1],
+            queue: upcoming[consumed..].iter().copied().collect(),
+            hold_piece,
+            hold_used: false,
+            randomizer,
+            score: new_score,
+            lines: new_lines,
+            level: self.level + rules.levels.level(rules.start_level, new_lines)
+                - rules.levels.level(rules.start_level, self.lines),
+            combo,
+            back_to_back,
+            lock_out: y + piece.height <= self.bits.hidden_rows(),
+            garbage_out,
+            pending_garbage,
+            garbage_sent,
+            board: new_board,
+            bits: new_bits,
+            hash: new_hash,
+            heights: new_heights,
+        }
     }
 }
 
 impl Default for Position {
     fn default() -> Self {
-        let board = [[Color::Empty; BOARD_WIDTH]; BOARD_HEIGHT];
+        let board = Board::new(BOARD_WIDTH, BOARD_HEIGHT, Color::Empty);
+        let bits = BitBoard::new(&board);
 
         // TODO: Fix random
+        let queue: Queue = [Color::J].into_iter().collect();
+
         Self {
-            last_piece: Color::Empty,
+            randomizer: dealt(RandomizerKind::Nes, Color::I, &queue),
             current_piece: Color::I,
-            next_piece: Color::J,
+            queue,
+            hold_piece: Color::Empty,
+            hold_used: false,
             score: 0,
-            hash: hash_board(&board),
-            board,
-            min_y: BOARD_HEIGHT - 1,
+            lines: 0,
+            level: 0,
+            combo: 0,
+            back_to_back: false,
+            lock_out: false,
+            garbage_out: false,
+            pending_garbage: GarbageQueue::new(),
+            garbage_sent: 0,
+            hash: hash_board(&bits),
+            heights: bits.heights(),
+            bits,
+            board: Some(Box::new(board)),
         }
     }
 }
 
-impl Distribution<Color> for Position {
-    fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> Color {
-        let roll = rng.gen_range(1..9);
+fn proximity(a: State, b: State, rot_dim: i32) -> i32 {
+    (a.0 - b.0).abs() + cmp::min(wrap_rot(a.2 - b.2, rot_dim), wrap_rot(b.2 - a.2, rot_dim))
+}
 
-        if roll == self.next_piece as u8 || roll == 8 {
-            let reroll = rng.gen_range(1..7);
-            Color::from(reroll)
-        } else {
-            Color::from(roll)
-        }
+fn dealt(kind: RandomizerKind, current_piece: Color, queue: &[Color]) -> Randomizer {
+    let mut randomizer = Randomizer::new(kind);
+    randomizer.push(current_piece);
+    for &piece in queue {
+        randomizer.push(piece);
     }
+    randomizer
 }
 
-impl fmt::Display for Position {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let mut empty_cells = 0;
-        for y in 0..BOARD_HEIGHT {
-            for x in 0..BOARD_WIDTH {
-                if !self.board[y][x].is_empty() && empty_cells > 0 && empty_cells < 10 {
-                    write!(f, "{}", empty_cells)?;
-                    empty_cells = 0;
-                }
+/// Rows above the visible field on a board `height` rows tall.
+fn hidden_rows(height: usize) -> usize {
+    height.saturating_sub(VISIBLE_HEIGHT).max(HIDDEN_ROWS)
+}
 
-                if !self.board[y][x].is_empty() {
-                    write!(f, "{}", self.board[y][x])?;
-                } else {
-                    empty_cells += 1;
-                }
-            }
+pub fn hash_board(bits: &BitBoard) -> u64 {
+    let mut hash = 0;
 
-            if empty_cells > 0 && empty_cells < 10 {
-                write!(f, "{}", empty_cells)?;
+    for (y, row) in ZOBRISTS.iter().enumerate().take(bits.height) {
+        for (x, zobrist) in row.iter().enumerate().take(bits.width) {
+            if bits.get(x, y) {
+                hash ^= zobrist;
             }
-
-            write!(f, "/")?;
-            empty_cells = 0;
         }
+    }
 
-        write!(
-            f,
-            " {} {} {}",
-            self.current_piece, self.next_piece, self.score
-        )?;
+    hash
+}
 
-        Ok(())
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+    use std::{hint::black_box, str::FromStr, time::Instant};
+
+    #[test]
+    fn test_empty_tpn() {
+        let pos = Position::default();
+        assert_eq!(
+            pos.to_string(),
+            format!(
+                "v2 ////////////////////// {} {} 0 - 0 0 0 0 - 0 nes:{}",
+                pos.current_piece,
+                pos.next_piece(),
+                pos.next_piece()
+            )
+        );
     }
-}
 
-// TODO: overflow error handling
-impl FromStr for Position {
-    type Err = ();
+    #[test]
+    fn test_hash_empty() {
+        let pos1 = Position::default();
+        let pos2 = Position::from_str(&pos1.to_string()).unwrap();
 
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let mut board = [[Color::Empty; BOARD_WIDTH]; BOARD_HEIGHT];
-        let mut curr_x = 0;
-        let mut curr_y = 0;
+        assert!(pos1.hash == pos2.hash)
+    }
 
-        let tokens: Vec<&str> = s.split(' ').collect();
-        if tokens.len() < 3 {
-            return Err(());
-        }
+    #[test]
+    fn test_hold_tpn() {
+        let pos = Position::from_str("////////////////////// T S 0 I*").unwrap();
+        assert_eq!(pos.hold_piece, Color::I);
+        assert!(pos.hold_used);
+        assert_eq!(
+            pos.to_string(),
+            "v2 ////////////////////// T S 0 I* 0 0 0 0 - 0 nes:S"
+        );
 
-        let board_tok = tokens[0];
-        let curr_piece_tok = tokens[1];
-        let next_piece_tok = tokens[2];
-        let score_tok = tokens[3];
+        let pos = Position::from_str("////////////////////// T S 0").unwrap();
+        assert_eq!(pos.hold_piece, Color::Empty);
+        assert!(!pos.hold_used);
+    }
 
-        let mut min_y_found = false;
-        let mut min_y = 0;
+    #[test]
+    fn test_queue_tpn() {
+        let pos = Position::from_str("////////////////////// T SZLO 0 -").unwrap();
+        assert_eq!(
+            pos.queue.as_slice(),
+            [Color::S, Color::Z, Color::L, Color::O]
+        );
+        assert_eq!(
+            pos.to_string(),
+            "v2 ////////////////////// T SZLO 0 - 0 0 0 0 - 0 nes:O"
+        );
 
-        for x in board_tok.chars() {
-            match x {
-                '/' => {
-                    curr_x = 0;
-                    curr_y += 1;
-                }
-                '1' | '2' | '3' | '4' | '5' | '6' | '7' | '8' | '9' => {
-                    if !min_y_found {
-                        min_y = curr_y;
-                        min_y_found = true;
-                    }
-                    curr_x += (x as usize) - ('0' as usize)
-                }
-                _ => {
-                    let piece = Color::try_from(x)?;
-                    board[curr_y][curr_x] = piece;
-                    curr_x += 1;
-                }
-            }
-        }
+        let next = pos.apply_move(
+            &Rules::default(),
+            pos.legal_moves(&Rules::default())[0][0],
+            None,
+        );
+        assert_eq!(next.current_piece, Color::S);
+        assert_eq!(
+            next.queue.as_slice(),
+            [Color::Z, Color::L, Color::O, Color::Random]
+        );
 
-        let current_piece = Color::try_from(curr_piece_tok.chars().next().ok_or(())?)?;
-        let next_piece = Color::try_from(next_piece_tok.chars().next().ok_or(())?)?;
-        let score = i64::from_str(score_tok).map_err(|_| ())?;
+        let pos = Position::from_str("////////////////////// T - 0 -").unwrap();
+        assert!(pos.queue.is_empty());
+        assert_eq!(
+            pos.to_string(),
+            "v2 ////////////////////// T - 0 - 0 0 0 0 - 0 nes:T"
+        );
+    }
 
-        let hash = hash_board(&board);
-        Ok(Position::new(
-            Color::Empty,
-            current_piece,
-            next_piece,
-            score,
-            board,
-            hash,
-            min_y,
-        ))
+    #[test]
+    fn test_hold_moves() {
+        let rules = Rules {
+            hold: true,
+            ..Rules::default()
+        };
+        let pos = Position::from_str("////////////////////// T S 0 I").unwrap();
+
+        let moves = &pos.legal_moves(&rules)[0];
+        let held: Vec<_> = moves.iter().filter(|mv| mv.held.is_some()).collect();
+        assert!(!held.is_empty());
+        assert!(held.iter().all(|mv| mv.piece == PieceKind::I));
+
+        let next = pos.apply_move(&rules, *held[0], None);
+        assert_eq!(next.hold_piece, Color::T);
+        assert_eq!(next.current_piece, Color::S);
+        assert_eq!(pos.placement_path(&rules, *held[0])[0], Action::Hold);
+
+        let pos = Position::from_str("////////////////////// T S 0 I*").unwrap();
+        assert!(pos.legal_moves(&rules)[0]
+            .iter()
+            .all(|mv| mv.held.is_none()));
+        assert!(Position::default().legal_moves(&Rules::default())[0]
+            .iter()
+            .all(|mv| mv.held.is_none()));
     }
-}
 
-fn check_collision<T: Cell>(board: &Board<T>, piece: &Piece, x: i32, y: i32) -> bool {
-    let size_x = piece[0].len() as i32;
-    let size_y = piece.len() as i32;
+    #[test]
+    fn test_hold_into_empty_slot() {
+        let rules = Rules {
+            hold: true,
+            ..Rules::default()
+        };
+        let pos = Position::default();
+
+        let held = pos.legal_moves(&rules)[0]
+            .iter()
+            .copied()
+            .find(|mv| mv.held.is_some())
+            .unwrap();
+        assert_eq!(Color::from(held.piece), pos.next_piece());
 
-    if x < 0 || x > BOARD_WIDTH as i32 - size_x || y < 0 || y > BOARD_HEIGHT as i32 - size_y {
-        return true;
+        let next = pos.apply_move(&rules, held, None);
+        assert_eq!(next.hold_piece, pos.current_piece);
+        assert_eq!(next.current_piece, Color::Random);
     }
 
-    for i in 0..size_x {
-        for j in 0..size_y {
-            if !board[(y + j) as usize][(x + i) as usize].is_empty()
-                && !piece[j as usize][i as usize].is_empty()
-            {
-                return true;
-            }
+    #[test]
+    fn test_srs_kick_reaches_placement() {
+        let srs = Rules {
+            rotation: RotationSystem::Srs,
+            ..Rules::default()
+        };
+        // The block at (4, 17) stops a T pointing up from dropping under it
+        let pos = Position::from_str(
+            "/////////////////1Z2Z5/3Z3Z2/ZZ2ZZ1Z1Z/1ZZ2ZZZZZ/ZZZZ1ZZ1ZZ/ T J 0 -",
+        )
+        .unwrap();
+        let tucked = Placement::new(PieceKind::T, 4, 17, 2);
+
+        assert!(!pos.legal_moves(&Rules::default())[0].contains(&tucked));
+        assert!(pos.legal_moves(&srs)[0].contains(&tucked));
+
+        let path = pos.placement_path(&srs, tucked);
+        assert!(
+            path.contains(&Action::RotateClockwise)
+                || path.contains(&Action::RotateCounterclockwise)
+        );
+    }
+
+    #[test]
+    fn test_path_cells() {
+        let rules = Rules {
+            rotation: RotationSystem::Srs,
+            hold: true,
+            ..Rules::default()
+        };
+        let pos = Position::from_str(
+            "/////////////////1Z2Z5/3Z3Z2/ZZ2ZZ1Z1Z/1ZZ2ZZZZZ/ZZZZ1ZZ1ZZ/ T L 0 -",
+        )
+        .unwrap();
+
+        // Replaying each path, kicks and hold included, ends on the placement
+        for placement in pos.legal_moves(&rules).swap_remove(0) {
+            let path = pos.placement_path(&rules, placement);
+            let cells = pos.path_cells(&rules, placement, &path);
+            assert_eq!(cells.len(), path.len() + 1);
+
+            let locked = placement
+                .piece
+                .shape(placement.rot as i32)
+                .cells()
+                .map(|(dx, dy)| ((placement.x + dx) as i32, (placement.y + dy) as i32));
+            assert_eq!(cells.last(), Some(&locked), "{:?}", placement);
         }
     }
 
-    false
-}
+    #[test]
+    fn test_srs_deduplicates_shapes() {
+        let srs = Rules {
+            rotation: RotationSystem::Srs,
+            ..Rules::default()
+        };
+        let pos = Position::from_str("////////////////////// S - 0 -").unwrap();
 
-fn is_lock_fast(
-    board: &Board<Color>,
-    piece: &Piece,
-    x: usize,
-    y: usize,
-    size_x: usize,
-    size_y: usize,
-) -> bool {
-    if y == BOARD_HEIGHT - size_y {
-        return !check_collision(board, piece, x as i32, y as i32);
-    }
-
-    let mut is_lock = false;
-
-    for j in (0..size_y).rev() {
-        for i in 0..size_x {
-            if piece[j as usize][i as usize] as u8 != 0 {
-                if board[(y + j) as usize][(x + i) as usize] as u8 != 0 {
-                    return false;
-                } else if board[(y + j + 1) as usize][(x + i) as usize] as u8 != 0 {
-                    is_lock = true;
-                }
-            }
-        }
+        // S has two distinct shapes, each placeable in 9 or 8 columns
+        assert_eq!(pos.legal_moves(&srs)[0].len(), 17);
+        assert_eq!(
+            pos.legal_moves(&srs)[0].len(),
+            pos.legal_moves(&Rules::default())[0].len()
+        );
     }
 
-    is_lock
-}
+    #[test]
+    fn test_hard_drop_path() {
+        let pos = Position::default();
+        let goal = Placement::new(PieceKind::I, 0, 21, 0);
+
+        for prefer_hard_drop in [false, true] {
+            let rules = Rules {
+                prefer_hard_drop,
+                ..Rules::default()
+            };
+            let path = pos.placement_path(&rules, goal);
+            assert_eq!(path.last(), Some(&Action::HardDrop));
+            assert!(!path.contains(&Action::SoftDrop));
+        }
 
-fn wrap_rot(rot: i32, dim: i32) -> i32 {
-    (rot % dim + dim) % dim
-}
+        // Tucking under the overhang needs soft drops and no hard drop
+        let pos = Position::from_str("////////////////////ZZZZ6// I J 0 -").unwrap();
+        let path = pos.placement_path(&Rules::default(), goal);
+        assert!(path.contains(&Action::SoftDrop));
+        assert_eq!(path.last(), Some(&Action::MoveLeft));
+    }
 
-fn proximity(a: (i32, i32, i32), b: (i32, i32, i32), rot_dim: i32) -> i32 {
-    (a.0 - b.0).abs() + cmp::min(wrap_rot(a.2 - b.2, rot_dim), wrap_rot(b.2 - a.2, rot_dim))
-}
+    #[test]
+    fn test_finesse_path() {
+        let finesse = Rules {
+            finesse: true,
+            ..Rules::default()
+        };
+        let das = Rules {
+            das: true,
+            ..finesse
+        };
+        let pos = Position::default();
+        let left_wall = Placement::new(PieceKind::I, 0, 21, 0);
 
-fn hash_board(board: &Board<Color>) -> u64 {
-    let mut hash = 0;
+        assert_eq!(
+            pos.placement_path(&finesse, left_wall),
+            [
+                Action::MoveLeft,
+                Action::MoveLeft,
+                Action::MoveLeft,
+                Action::HardDrop
+            ]
+        );
+        assert_eq!(
+            pos.placement_path(&das, left_wall),
+            [Action::DasLeft, Action::HardDrop]
+        );
 
-    for x in 0..BOARD_WIDTH {
-        for y in 0..BOARD_HEIGHT {
-            let piece = board[y][x];
-            if !piece.is_empty() {
-                hash ^= ZOBRISTS[y][x];
-            }
+        let pos = Position::from_str(
+            "/////////////////1Z2Z5/3Z3Z2/ZZ2ZZ1Z1Z/1ZZ2ZZZZZ/ZZZZ1ZZ1ZZ/ T J 0 -",
+        )
+        .unwrap();
+        for &placement in pos.legal_moves(&finesse)[0].iter() {
+            let default_len = pos.placement_path(&Rules::default(), placement).len();
+            assert!(pos.placement_path(&finesse, placement).len() <= default_len);
+            assert!(pos.placement_path(&das, placement).len() <= default_len);
         }
     }
 
-    hash
-}
+    #[test]
+    fn test_guideline_scoring_state() {
+        let rules = Rules {
+            scoring: ScoringSystem::Guideline,
+            ..Rules::default()
+        };
+        let pos =
+            Position::from_str("///////////////////3Z6/ZZZ3ZZZZ/ZZZZ1ZZZZZ/ T I 0 - 2 1").unwrap();
+        assert_eq!(pos.combo, 2);
+        assert!(pos.back_to_back);
+
+        let tsd = Placement::new(PieceKind::T, 3, 20, 0);
+        assert_eq!(pos.spin(tsd), Spin::Full);
+        assert_eq!(pos.spin(Placement::new(PieceKind::T, 0, 18, 0)), Spin::None);
+
+        // Back-to-back T-spin double as the third clear in a row
+        let next = pos.apply_move(&rules, tsd, None);
+        assert_eq!(next.score, 1200 * 3 / 2 + 50 * 2);
+        assert_eq!(next.combo, 3);
+        assert!(next.back_to_back);
+        assert!(next.to_string().contains(" 3 1 2 0 "));
+
+        // Clearing nothing ends the combo but keeps back-to-back going
+        let next = next.apply_move(&rules, Placement::new(PieceKind::I, 6, 21, 0), None);
+        assert!(next.back_to_back);
+        assert_eq!(next.combo, 0);
+    }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    #[test]
+    fn test_level_progression() {
+        let rules = Rules::default();
+        let pos = Position::from_str("/////////////////////ZZZZZZ4/ I J 0 - 0 0 9 0").unwrap();
+        assert_eq!((pos.lines, pos.level), (9, 0));
+
+        let next = pos.apply_move(&rules, Placement::new(PieceKind::I, 6, 21, 0), None);
+        assert_eq!((next.lines, next.level), (10, 1));
+        assert!(next.to_string().contains(" 10 1 - 0 "));
+
+        // A level given by the position is kept and only moved by transitions
+        let pos = Position::from_str("/////////////////////ZZZZZZ4/ I J 0 - 0 0 9 5").unwrap();
+        let next = pos.apply_move(&rules, Placement::new(PieceKind::I, 6, 21, 0), None);
+        assert_eq!(next.level, 6);
+    }
 
     #[test]
-    fn test_empty_tpn() {
+    fn test_level_gravity_reachability() {
+        let rules = Rules {
+            timing: Some(Timing {
+                gravity: 48,
+                das: 16,
+                arr: 6,
+                input_interval: 2,
+                lock_delay: 0,
+            }),
+            level_gravity: true,
+            ..Rules::default()
+        };
+        let tpn = format!("/////{} I J 0 -", "ZZZZZZZZZ1/".repeat(17));
+        let mut pos = Position::from_str(&tpn).unwrap();
+        let all = pos.legal_moves(&Rules::default())[0].clone();
+        assert_eq!(pos.legal_moves(&rules)[0], all);
+        assert!(all.iter().any(|placement| placement.x == 0));
+
+        // At a row per frame the piece locks on the stack before it gets far
+        pos.level = 29;
+        let fast = pos.legal_moves(&rules)[0].clone();
+        assert!(!fast.is_empty());
+        assert!(fast.iter().all(|placement| placement.x > 0));
+    }
+
+    #[test]
+    fn test_game_over() {
+        let rules = Rules::default();
         let pos = Position::default();
+        assert_eq!(pos.is_game_over(&rules), None);
+
+        // A piece locking in the hidden rows
+        let hidden = pos.apply_move(&rules, Placement::new(PieceKind::O, 0, 0, 0), None);
+        assert_eq!(hidden.is_game_over(&rules), Some(GameOver::LockOut));
+        let visible = pos.apply_move(&rules, Placement::new(PieceKind::O, 0, 1, 0), None);
+        assert_eq!(visible.is_game_over(&rules), None);
+
+        // The I spawns in the second row
+        let blocked = Position::from_str("/4Z5///////////////////// I J 0 -").unwrap();
+        assert_eq!(blocked.is_game_over(&rules), Some(GameOver::BlockOut));
+        assert!(blocked.legal_moves(&rules)[0].is_empty());
+
+        let top = Position::from_str("Z9////////////////////// I J 0 -").unwrap();
+        assert_eq!(top.is_game_over(&rules), None);
+        let top_out = Rules {
+            top_out: true,
+            ..rules
+        };
+        assert_eq!(top.is_game_over(&top_out), Some(GameOver::TopOut));
+    }
+
+    #[test]
+    fn test_board_sizes() {
+        let tpn = format!(
+            "v2 10x40:{}4ZZZZ2/ T I 0 - 0 0 0 0 - 0 nes:I",
+            "/".repeat(39)
+        );
+        let tall = Position::from_str(&tpn).unwrap();
+        assert_eq!(tall.to_string(), tpn);
+        assert_eq!(tall.bits.hidden_rows(), 20);
+        assert_eq!(tall.spawn(&Rules::default(), PieceKind::I), (3, 19, 0));
+
+        // Runs longer than 9 cells take several digits
+        let tpn = format!("v2 16x12:96Z{} I J 0 - 0 0 0 0 - 0 nes:J", "/".repeat(12));
+        let wide = Position::from_str(&tpn).unwrap();
+        assert_eq!(wide.board()[0][15], Color::Z);
+        assert_eq!(wide.to_string(), tpn);
+
+        assert!(Position::from_str("17x22:////// I J 0 -").is_err());
+        assert!(Position::from_str("4x4:ZZZZZ//// I J 0 -").is_err());
+    }
+
+    #[test]
+    fn test_narrow_board() {
+        let rules = Rules::default();
+        let pos = Position::from_str("4x8://////// I O 0 -").unwrap();
+        assert_eq!(pos.spawn(&rules, PieceKind::I), (0, 1, 0));
+
+        // Flat on the floor or standing in any column
+        let moves = pos.legal_moves(&rules).swap_remove(0);
+        assert_eq!(moves.len(), 5);
+
+        let flat = Placement::new(PieceKind::I, 0, 7, 0);
+        let pos = pos.apply_move(&rules, flat, None);
+        assert_eq!(pos.lines, 1);
         assert_eq!(
             pos.to_string(),
-            format!(
-                "////////////////////// {} {} 0",
-                pos.current_piece, pos.next_piece
-            )
+            "v2 4x8://////// O ? 40 - 1 0 1 0 - 0 nes:O"
         );
     }
 
     #[test]
-    fn test_hash_empty() {
-        let pos1 = Position::default();
-        let pos2 = Position::from_str(&pos1.to_string()).unwrap();
+    fn test_features() {
+        let pos = Position::from_str("////////////////////Z9/1Z8/ I J 0 -").unwrap();
+        let features = pos.features();
 
-        assert!(pos1.hash == pos2.hash)
+        assert_eq!(features.holes, 1.);
+        assert_eq!(features.aggregate_height, 3.);
+        assert_eq!(features.bumpiness, 2.);
+    }
+
+    #[test]
+    fn test_line_clear() {
+        let rules = Rules::default();
+        let pos = Position::from_str("///////////////////T9/ZZZZZZ4/SS1ZZZZZZZ/ I J 0 -").unwrap();
+        let pos = pos.apply_move(&rules, Placement::new(PieceKind::I, 6, 20, 0), None);
+
+        // The rows above the cleared one keep their colours
+        assert_eq!(pos.lines, 1);
+        let board = pos.to_string();
+        assert!(board.starts_with("v2 ////////////////////T9/SS1ZZZZZZZ/ J "));
+        assert_eq!(pos.hash, Position::from_str(&board).unwrap().hash);
+        assert_eq!(pos.bits, BitBoard::new(pos.board()));
+    }
+
+    #[test]
+    fn test_garbage_rises() {
+        let rules = Rules::default();
+        let tpn = format!("v2 {}ZZZZZZ4/ I J 0 - 0 0 0 0 2@9 0 nes:J", "/".repeat(21));
+        let pos = Position::from_str(&tpn).unwrap();
+        assert_eq!(pos.to_string(), tpn);
+
+        // Nothing cleared, so the garbage rises under the stack
+        let pos = pos.apply_move(&rules, Placement::new(PieceKind::I, 0, 20, 0), None);
+        assert!(pos.pending_garbage.is_empty());
+        assert!(pos.to_string().starts_with(&format!(
+            "v2 {}IIII6/ZZZZZZ4/GGGGGGGGG1/GGGGGGGGG1/ J ",
+            "/".repeat(18)
+        )));
+        assert_eq!(pos.hash, hash_board(&BitBoard::new(pos.board())));
+        assert_eq!(pos.bits, BitBoard::new(pos.board()));
+        assert_eq!(pos.is_game_over(&rules), None);
+    }
+
+    #[test]
+    fn test_garbage_cancel() {
+        let rules = Rules {
+            garbage: true,
+            ..Rules::default()
+        };
+        let tpn = format!(
+            "{}Z9/{} I J 0 - 0 0 0 0 1@3,2@0 0",
+            "/".repeat(17),
+            "ZZZZZZZZZ1/".repeat(4)
+        );
+        let pos = Position::from_str(&tpn).unwrap();
+
+        // A tetris cancels the three pending lines and sends the last one
+        let pos = pos.apply_move(&rules, Placement::new(PieceKind::I, 9, 18, 1), None);
+        assert_eq!(pos.lines, 4);
+        assert!(pos.pending_garbage.is_empty());
+        assert_eq!(pos.garbage_sent, 1);
+        assert!(pos.to_string().contains(" 4 0 - 1 "));
+    }
+
+    #[test]
+    fn test_garbage_out() {
+        let rules = Rules::default();
+        let mut pos = Position::from_str(&format!("/Z9{} I J 0 -", "/".repeat(21))).unwrap();
+        pos.receive_garbage(Garbage { lines: 2, hole: 20 });
+        assert_eq!(pos.pending_garbage[0].hole, 9);
+
+        let pos = pos.apply_move(&rules, Placement::new(PieceKind::I, 0, 21, 0), None);
+        assert!(pos.garbage_out);
+        assert_eq!(pos.is_game_over(&rules), Some(GameOver::TopOut));
+        assert!(Position::from_str("////////////////////// I J 0 - 0 0 0 0 1@10 0").is_err());
+    }
+
+    #[test]
+    fn test_heights() {
+        let rules = Rules::default();
+        let tpn = format!("{}ZZZZZZZZZ1/Z9/1ZZZZZZZZ1/ I J 0 -", "/".repeat(19));
+        let pos = Position::from_str(&tpn).unwrap();
+        assert_eq!(pos.heights(), [3, 3, 3, 3, 3, 3, 3, 3, 3, 0]);
+
+        // Columns whose top cell clears fall through to what is under it
+        let pos = pos.apply_move(&rules, Placement::new(PieceKind::I, 9, 18, 1), None);
+        assert_eq!(pos.lines, 1);
+        assert_eq!(pos.heights(), [2, 1, 1, 1, 1, 1, 1, 1, 1, 3]);
+        assert_eq!(pos.stack_height(), 3);
+
+        // Random games with garbage, `apply_move` checking the heights
+        let mut rng = SmallRng::seed_from_u64(24);
+        let mut pos = Position::default();
+        for _ in 0..150 {
+            if pos.is_game_over(&rules).is_some() {
+                pos = Position::default();
+            }
+            if rng.gen_bool(0.3) {
+                let hole = rng.gen_range(0..pos.width());
+                pos.receive_garbage(Garbage {
+                    lines: rng.gen_range(1..4),
+                    hole,
+                });
+            }
+
+            let moves = pos.legal_moves(&rules).swap_remove(0);
+            let placement = moves[rng.gen_range(0..moves.len())];
+            pos = pos.apply_move(&rules, placement, Some(&mut rng));
+            assert_eq!(pos.heights, pos.bits.heights());
+        }
+    }
+
+    #[test]
+    fn test_uncolored() {
+        let rules = Rules {
+            garbage: true,
+            ..Rules::default()
+        };
+        let mut rng = SmallRng::seed_from_u64(18);
+        let mut pos = Position::default();
+        pos.receive_garbage(Garbage { lines: 3, hole: 4 });
+
+        // Search positions play the same game, only without the colours
+        let mut uncolored = pos.uncolored();
+        while pos.is_game_over(&rules).is_none() && pos.lines < 20 {
+            let moves = pos.legal_moves(&rules).swap_remove(0);
+            let placement = moves[rng.gen_range(0..moves.len())];
+            pos = pos.apply_move(&rules, placement, None);
+            uncolored = uncolored.apply_move(&rules, placement, None);
+
+            assert_eq!(pos.bits, BitBoard::new(pos.board()));
+            assert_eq!(
+                (uncolored.bits, uncolored.hash, uncolored.score),
+                (pos.bits, pos.hash, pos.score)
+            );
+            assert!(uncolored.board.is_none());
+        }
+    }
+
+    /// Run with `cargo test --release bench_apply_move -- --ignored --nocapture`.
+    #[test]
+    #[ignore]
+    fn bench_apply_move() {
+        const ROUNDS: u32 = 20_000;
+
+        let rules = Rules::default();
+        let tpn = format!("{}ZZ1ZZZZ3/ZZZZ1ZZZZ1/ZZZZZ1ZZZ1/ T L 0 -", "/".repeat(19));
+        let pos = Position::from_str(&tpn).unwrap();
+        let placements = pos.legal_moves(&rules).swap_remove(0);
+
+        for (name, pos) in [("colored", pos.clone()), ("uncolored", pos.uncolored())] {
+            let start = Instant::now();
+            for _ in 0..ROUNDS {
+                for &placement in &placements {
+                    black_box(black_box(&pos).apply_move(&rules, placement, None));
+                }
+            }
+            let moves = ROUNDS * placements.len() as u32;
+            println!("{}: {:?} per move", name, start.elapsed() / moves);
+        }
     }
 }
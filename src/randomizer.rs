@@ -0,0 +1,394 @@
+use std::{fmt, str::FromStr};
+
+use rand::{distributions::Distribution, Rng};
+
+use crate::pos::{Color, PIECE_ARRAY};
+
+const TGM_ROLLS: i32 = 6;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RandomizerKind {
+    Nes,
+    Bag,
+    Uniform,
+    Tgm,
+}
+
+pub const RANDOMIZER_NAMES: [&str; 4] = ["nes", "bag", "uniform", "tgm"];
+
+impl fmt::Display for RandomizerKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            RandomizerKind::Nes => "nes",
+            RandomizerKind::Bag => "bag",
+            RandomizerKind::Uniform => "uniform",
+            RandomizerKind::Tgm => "tgm",
+        };
+
+        write!(f, "{}", name)
+    }
+}
+
+impl FromStr for RandomizerKind {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "nes" => Ok(RandomizerKind::Nes),
+            "bag" => Ok(RandomizerKind::Bag),
+            "uniform" => Ok(RandomizerKind::Uniform),
+            "tgm" => Ok(RandomizerKind::Tgm),
+            _ => Err(()),
+        }
+    }
+}
+
+/// State of a piece randomizer after dealing every known piece of a position.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Randomizer {
+    /// Roll 8 outcomes, reroll once on a repeat or on the 8th outcome.
+    Nes {
+        last: Color,
+    },
+    /// Bit `i` is set while `PIECE_ARRAY[i]` is still in the current bag.
+    Bag {
+        remaining: u8,
+    },
+    Uniform,
+    /// Roll up to 6 times to avoid the last 4 pieces, keeping the last roll.
+    Tgm {
+        history: [Color; 4],
+    },
+}
+
+impl Randomizer {
+    pub fn new(kind: RandomizerKind) -> Self {
+        match kind {
+            RandomizerKind::Nes => Randomizer::Nes { last: Color::Empty },
+            RandomizerKind::Bag => Randomizer::Bag {
+                remaining: FULL_BAG,
+            },
+            RandomizerKind::Uniform => Randomizer::Uniform,
+            RandomizerKind::Tgm => Randomizer::Tgm {
+                history: [Color::Z, Color::S, Color::S, Color::Z],
+            },
+        }
+    }
+
+    pub fn kind(&self) -> RandomizerKind {
+        match self {
+            Randomizer::Nes { .. } => RandomizerKind::Nes,
+            Randomizer::Bag { .. } => RandomizerKind::Bag,
+            Randomizer::Uniform => RandomizerKind::Uniform,
+            Randomizer::Tgm { .. } => RandomizerKind::Tgm,
+        }
+    }
+
+    /// Probability of each piece of `PIECE_ARRAY` being dealt next.
+    pub fn probabilities(&self) -> [f64; 7] {
+        match *self {
+            Randomizer::Nes { last } => {
+                let mut probs = [0.; 7];
+                for (i, &piece) in PIECE_ARRAY.iter().enumerate() {
+                    // The reroll happens with probability 1/8, or 2/8 when
+                    // `last` is a piece.
+                    probs[i] = if last == Color::Empty {
+                        1. / 7.
+                    } else if piece == last {
+                        1. / 28.
+                    } else {
+                        9. / 56.
+                    };
+                }
+                probs
+            }
+            Randomizer::Bag { remaining } => {
+                let bag = bag_or_refill(remaining);
+                let count = bag.count_ones() as f64;
+                let mut probs = [0.; 7];
+                for (i, prob) in probs.iter_mut().enumerate() {
+                    if bag & (1 << i) != 0 {
+                        *prob = 1. / count;
+                    }
+                }
+                probs
+            }
+            Randomizer::Uniform => [1. / 7.; 7],
+            Randomizer::Tgm { history } => {
+                let mut seen = 0u8;
+                for piece in history {
+                    seen |= piece_bit(piece);
+                }
+
+                let r = seen.count_ones() as f64 / 7.;
+                let fresh = (0..TGM_ROLLS).map(|i| r.powi(i)).sum::<f64>() / 7.;
+                let stale = r.powi(TGM_ROLLS - 1) / 7.;
+
+                let mut probs = [0.; 7];
+                for (i, prob) in probs.iter_mut().enumerate() {
+                    *prob = if seen & (1 << i) != 0 { stale } else { fresh };
+                }
+                probs
+            }
+        }
+    }
+
+    pub fn probability(&self, piece: Color) -> f64 {
+        match piece_index(piece) {
+            Some(i) => self.probabilities()[i],
+            None => 0.,
+        }
+    }
+
+    /// Records `piece` as the latest dealt piece.
+    pub fn push(&mut self, piece: Color) {
+        let Some(i) = piece_index(piece) else {
+            return;
+        };
+
+        match self {
+            Randomizer::Nes { last } => *last = piece,
+            Randomizer::Bag { remaining } => {
+                let mut bag = bag_or_refill(*remaining);
+                // A piece missing from the bag can only start a new one
+                if bag & (1 << i) == 0 {
+                    bag = FULL_BAG;
+                }
+                *remaining = bag & !(1 << i);
+            }
+            Randomizer::Uniform => {}
+            Randomizer::Tgm { history } => {
+                history.rotate_left(1);
+                history[3] = piece;
+            }
+        }
+    }
+
+    pub fn deal<R: Rng + ?Sized>(&mut self, rng: &mut R) -> Color {
+        let piece = self.sample(rng);
+        self.push(piece);
+        piece
+    }
+}
+
+impl Distribution<Color> for Randomizer {
+    fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> Color {
+        match *self {
+            Randomizer::Nes { last } => {
+                let roll = rng.gen_range(0..8);
+
+                if roll == 7 || PIECE_ARRAY[roll] == last {
+                    PIECE_ARRAY[rng.gen_range(0..7)]
+                } else {
+                    PIECE_ARRAY[roll]
+                }
+            }
+            Randomizer::Bag { remaining } => {
+                let bag = bag_or_refill(remaining);
+                let mut pick = rng.gen_range(0..bag.count_ones());
+                let mut i = 0;
+                loop {
+                    if bag & (1 << i) != 0 {
+                        if pick == 0 {
+                            return PIECE_ARRAY[i];
+                        }
+                        pick -= 1;
+                    }
+                    i += 1;
+                }
+            }
+            Randomizer::Uniform => PIECE_ARRAY[rng.gen_range(0..7)],
+            Randomizer::Tgm { history } => {
+                let mut piece = PIECE_ARRAY[rng.gen_range(0..7)];
+                for _ in 1..TGM_ROLLS {
+                    if !history.contains(&piece) {
+                        break;
+                    }
+                    piece = PIECE_ARRAY[rng.gen_range(0..7)];
+                }
+                piece
+            }
+        }
+    }
+}
+
+/// The kind followed by its state, as in `nes:T`, `bag:OJS`, `uniform` or
+/// `tgm:ZSSZ`, with `-` for no piece.
+impl fmt::Display for Randomizer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.kind())?;
+
+        match *self {
+            Randomizer::Nes { last: Color::Empty } => write!(f, ":-"),
+            Randomizer::Nes { last } => write!(f, ":{}", last),
+            Randomizer::Bag { remaining: 0 } => write!(f, ":-"),
+            Randomizer::Bag { remaining } => {
+                write!(f, ":")?;
+                for (i, piece) in PIECE_ARRAY.iter().enumerate() {
+                    if remaining & (1 << i) != 0 {
+                        write!(f, "{}", piece)?;
+                    }
+                }
+                Ok(())
+            }
+            Randomizer::Uniform => Ok(()),
+            Randomizer::Tgm { history } => {
+                write!(f, ":")?;
+                for piece in history {
+                    write!(f, "{}", piece)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl FromStr for Randomizer {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (kind, state) = s.split_once(':').unwrap_or((s, ""));
+        let pieces = || -> Result<Vec<Color>, ()> {
+            match state {
+                "-" => Ok(Vec::new()),
+                _ => state
+                    .chars()
+                    .map(|c| {
+                        let piece = Color::try_from(c)?;
+                        piece_index(piece).ok_or(())?;
+                        Ok(piece)
+                    })
+                    .collect(),
+            }
+        };
+
+        match (kind.parse()?, pieces()?.as_slice()) {
+            (RandomizerKind::Nes, []) if state == "-" => Ok(Randomizer::Nes { last: Color::Empty }),
+            (RandomizerKind::Nes, &[last]) => Ok(Randomizer::Nes { last }),
+            (RandomizerKind::Bag, pieces) if !state.is_empty() => {
+                let remaining = pieces.iter().fold(0, |bag, &piece| bag | piece_bit(piece));
+                if remaining.count_ones() as usize != pieces.len() {
+                    return Err(());
+                }
+                Ok(Randomizer::Bag { remaining })
+            }
+            (RandomizerKind::Uniform, []) if state.is_empty() => Ok(Randomizer::Uniform),
+            (RandomizerKind::Tgm, &[a, b, c, d]) => Ok(Randomizer::Tgm {
+                history: [a, b, c, d],
+            }),
+            _ => Err(()),
+        }
+    }
+}
+
+const FULL_BAG: u8 = 0b111_1111;
+
+fn bag_or_refill(remaining: u8) -> u8 {
+    if remaining == 0 {
+        FULL_BAG
+    } else {
+        remaining
+    }
+}
+
+fn piece_index(piece: Color) -> Option<usize> {
+    PIECE_ARRAY.iter().position(|&p| p == piece)
+}
+
+fn piece_bit(piece: Color) -> u8 {
+    piece_index(piece).map_or(0, |i| 1 << i)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{rngs::SmallRng, SeedableRng};
+
+    const KINDS: [RandomizerKind; 4] = [
+        RandomizerKind::Nes,
+        RandomizerKind::Bag,
+        RandomizerKind::Uniform,
+        RandomizerKind::Tgm,
+    ];
+
+    #[test]
+    fn test_probabilities_sum_to_one() {
+        let mut rng = SmallRng::seed_from_u64(7);
+
+        for kind in KINDS {
+            let mut randomizer = Randomizer::new(kind);
+            for _ in 0..20 {
+                let total: f64 = randomizer.probabilities().iter().sum();
+                assert!((total - 1.).abs() < 1e-9, "{} sums to {}", kind, total);
+                randomizer.deal(&mut rng);
+            }
+        }
+    }
+
+    #[test]
+    fn test_probabilities_match_sampling() {
+        let mut rng = SmallRng::seed_from_u64(11);
+
+        for kind in KINDS {
+            let mut randomizer = Randomizer::new(kind);
+            randomizer.push(Color::T);
+            randomizer.push(Color::L);
+
+            let n = 200_000;
+            let mut counts = [0; 7];
+            for _ in 0..n {
+                counts[piece_index(randomizer.sample(&mut rng)).unwrap()] += 1;
+            }
+
+            for (count, prob) in counts.iter().zip(randomizer.probabilities()) {
+                let freq = *count as f64 / n as f64;
+                assert!((freq - prob).abs() < 0.01, "{}: {} vs {}", kind, freq, prob);
+            }
+        }
+    }
+
+    #[test]
+    fn test_bag_deals_each_piece_once() {
+        let mut rng = SmallRng::seed_from_u64(3);
+        let mut randomizer = Randomizer::new(RandomizerKind::Bag);
+
+        for _ in 0..3 {
+            let mut bag: Vec<Color> = (0..7).map(|_| randomizer.deal(&mut rng)).collect();
+            bag.sort_by_key(|&piece| piece as u8);
+            assert_eq!(bag, PIECE_ARRAY);
+        }
+    }
+
+    #[test]
+    fn test_state_round_trip() {
+        let mut rng = SmallRng::seed_from_u64(5);
+
+        for kind in KINDS {
+            let mut randomizer = Randomizer::new(kind);
+            for _ in 0..10 {
+                assert_eq!(randomizer.to_string().parse(), Ok(randomizer));
+                randomizer.deal(&mut rng);
+            }
+        }
+
+        assert_eq!("nes:-".parse(), Ok(Randomizer::new(RandomizerKind::Nes)));
+        assert_eq!(
+            "bag:OT".parse(),
+            Ok(Randomizer::Bag {
+                remaining: 0b10_0010
+            })
+        );
+        for bad in [
+            "nes",
+            "nes:TT",
+            "bag:OO",
+            "bag",
+            "uniform:T",
+            "tgm:ZSS",
+            "tgm:ZSS?",
+            "x:-",
+        ] {
+            assert_eq!(bad.parse::<Randomizer>(), Err(()), "{}", bad);
+        }
+    }
+}
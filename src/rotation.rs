@@ -0,0 +1,243 @@
+use std::{fmt, str::FromStr};
+
+use arrayvec::ArrayVec;
+
+use crate::piece::PieceKind;
+
+pub type State = (i32, i32, i32);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RotationSystem {
+    /// The original kick-less offsets, with NES orientations.
+    Classic,
+    Srs,
+}
+
+pub const ROTATION_SYSTEM_NAMES: [&str; 2] = ["classic", "srs"];
+
+impl fmt::Display for RotationSystem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            RotationSystem::Classic => "classic",
+            RotationSystem::Srs => "srs",
+        };
+
+        write!(f, "{}", name)
+    }
+}
+
+impl FromStr for RotationSystem {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "classic" => Ok(RotationSystem::Classic),
+            "srs" => Ok(RotationSystem::Srs),
+            _ => Err(()),
+        }
+    }
+}
+
+// SRS data, y pointing up as in the guideline tables.
+type Kicks = [[[(i32, i32); 5]; 2]; 4];
+
+#[rustfmt::skip]
+const JLSTZ_KICKS: Kicks = [
+    [[(0, 0), (-1, 0), (-1, 1), (0, -2), (-1, -2)], [(0, 0), (1, 0), (1, 1), (0, -2), (1, -2)]],
+    [[(0, 0), (1, 0), (1, -1), (0, 2), (1, 2)], [(0, 0), (1, 0), (1, -1), (0, 2), (1, 2)]],
+    [[(0, 0), (1, 0), (1, 1), (0, -2), (1, -2)], [(0, 0), (-1, 0), (-1, 1), (0, -2), (-1, -2)]],
+    [[(0, 0), (-1, 0), (-1, -1), (0, 2), (-1, 2)], [(0, 0), (-1, 0), (-1, -1), (0, 2), (-1, 2)]],
+];
+
+#[rustfmt::skip]
+const I_KICKS: Kicks = [
+    [[(0, 0), (-2, 0), (1, 0), (-2, -1), (1, 2)], [(0, 0), (-1, 0), (2, 0), (-1, 2), (2, -1)]],
+    [[(0, 0), (-1, 0), (2, 0), (-1, 2), (2, -1)], [(0, 0), (2, 0), (-1, 0), (2, 1), (-1, -2)]],
+    [[(0, 0), (2, 0), (-1, 0), (2, 1), (-1, -2)], [(0, 0), (1, 0), (-2, 0), (1, -2), (-2, 1)]],
+    [[(0, 0), (1, 0), (-2, 0), (1, -2), (-2, 1)], [(0, 0), (-2, 0), (1, 0), (-2, -1), (1, 2)]],
+];
+
+/// Box size and cells of each piece in SRS spawn orientation, y pointing down.
+#[rustfmt::skip]
+const SRS_SPAWN_CELLS: [(i32, [(i32, i32); 4]); 7] = [
+    (4, [(0, 1), (1, 1), (2, 1), (3, 1)]),
+    (2, [(0, 0), (1, 0), (0, 1), (1, 1)]),
+    (3, [(0, 0), (0, 1), (1, 1), (2, 1)]),
+    (3, [(2, 0), (0, 1), (1, 1), (2, 1)]),
+    (3, [(1, 0), (2, 0), (0, 1), (1, 1)]),
+    (3, [(1, 0), (0, 1), (1, 1), (2, 1)]),
+    (3, [(0, 0), (1, 0), (1, 1), (2, 1)]),
+];
+
+/// SRS state of rotation index 0, which keeps the NES orientations.
+const SRS_SHIFT: [i32; 7] = [0, 0, 2, 2, 0, 2, 0];
+
+/// Classic moves of the shape's top left corner when rotating clockwise
+/// out of each rotation index.
+#[rustfmt::skip]
+const ROTATION_OFFSETS: [&[(i32, i32)]; 7] = [
+    &[(2, -2), (-2, 2)],
+    &[(0, 0)],
+    &[(0, -1), (0, 0), (1, 0), (-1, 1)],
+    &[(0, -1), (0, 0), (1, 0), (-1, 1)],
+    &[(1, -1), (-1, 1)],
+    &[(0, -1), (0, 0), (1, 0), (-1, 1)],
+    &[(1, -1), (-1, 1)],
+];
+
+const SPAWNS: [State; 7] = [
+    (3, 1, 0),
+    (4, 0, 0),
+    (3, 0, 0),
+    (3, 0, 0),
+    (3, 0, 0),
+    (3, 0, 0),
+    (3, 0, 0),
+];
+
+/// Position of the tight piece shape inside its SRS box, per SRS state.
+const SRS_OFFSETS: [[(i32, i32); 4]; 7] = {
+    let mut offsets = [[(0, 0); 4]; 7];
+
+    let mut piece = 0;
+    while piece < SRS_SPAWN_CELLS.len() {
+        let (size, mut cells) = SRS_SPAWN_CELLS[piece];
+
+        let mut state = 0;
+        while state < 4 {
+            let mut min = cells[0];
+            let mut i = 1;
+            while i < cells.len() {
+                if cells[i].0 < min.0 {
+                    min.0 = cells[i].0;
+                }
+                if cells[i].1 < min.1 {
+                    min.1 = cells[i].1;
+                }
+                i += 1;
+            }
+            offsets[piece][state] = min;
+
+            // Clockwise turn inside the box
+            let mut i = 0;
+            while i < cells.len() {
+                let (x, y) = cells[i];
+                cells[i] = (size - 1 - y, x);
+                i += 1;
+            }
+            state += 1;
+        }
+        piece += 1;
+    }
+
+    offsets
+};
+
+impl RotationSystem {
+    /// Number of distinct rotation states of `piece`.
+    pub fn rotations(&self, piece: PieceKind) -> i32 {
+        match self {
+            RotationSystem::Classic => ROTATION_OFFSETS[piece.index()].len() as i32,
+            RotationSystem::Srs if piece == PieceKind::O => 1,
+            RotationSystem::Srs => 4,
+        }
+    }
+
+    pub fn spawn(&self, piece: PieceKind) -> State {
+        let idx = piece.index();
+
+        match self {
+            RotationSystem::Classic => SPAWNS[idx],
+            RotationSystem::Srs => {
+                let box_x = if piece == PieceKind::O { 4 } else { 3 };
+                let offset = SRS_OFFSETS[idx][0];
+                let rot = wrap_rot(-SRS_SHIFT[idx], self.rotations(piece));
+
+                (box_x + offset.0, offset.1, rot)
+            }
+        }
+    }
+
+    /// Candidate states, in test order, after rotating `piece` from `state`
+    /// clockwise (`dir == 1`) or counterclockwise (`dir == -1`).
+    pub fn kicks(&self, piece: PieceKind, (x, y, rot): State, dir: i32) -> ArrayVec<State, 5> {
+        let idx = piece.index();
+        let rot_num = self.rotations(piece);
+        let new_rot = wrap_rot(rot + dir, rot_num);
+        let mut kicks = ArrayVec::new();
+
+        match self {
+            RotationSystem::Classic => {
+                let offset = if dir > 0 {
+                    ROTATION_OFFSETS[idx][rot as usize]
+                } else {
+                    let (dx, dy) = ROTATION_OFFSETS[idx][new_rot as usize];
+                    (-dx, -dy)
+                };
+
+                kicks.push((x + offset.0, y + offset.1, new_rot));
+            }
+            RotationSystem::Srs if piece == PieceKind::O => kicks.push((x, y, rot)),
+            RotationSystem::Srs => {
+                let state = wrap_rot(rot + SRS_SHIFT[idx], 4) as usize;
+                let new_state = wrap_rot(state as i32 + dir, 4) as usize;
+                let from = SRS_OFFSETS[idx][state];
+                let to = SRS_OFFSETS[idx][new_state];
+                let table = if piece == PieceKind::I {
+                    &I_KICKS
+                } else {
+                    &JLSTZ_KICKS
+                };
+
+                for (kx, ky) in table[state][(dir < 0) as usize] {
+                    kicks.push((x - from.0 + to.0 + kx, y - from.1 + to.1 - ky, new_rot));
+                }
+            }
+        }
+
+        kicks
+    }
+}
+
+pub fn wrap_rot(rot: i32, dim: i32) -> i32 {
+    (rot % dim + dim) % dim
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_classic_round_trip() {
+        for piece in PieceKind::ALL {
+            let system = RotationSystem::Classic;
+            for rot in 0..system.rotations(piece) {
+                let cw = system.kicks(piece, (4, 10, rot), 1)[0];
+                assert_eq!(system.kicks(piece, cw, -1)[0], (4, 10, rot));
+            }
+        }
+    }
+
+    #[test]
+    fn test_srs_spawns() {
+        let system = RotationSystem::Srs;
+
+        assert_eq!(system.spawn(PieceKind::I), (3, 1, 0));
+        assert_eq!(system.spawn(PieceKind::O), (4, 0, 0));
+        assert_eq!(system.spawn(PieceKind::T), (3, 0, 2));
+        assert_eq!(system.spawn(PieceKind::S), (3, 0, 0));
+    }
+
+    #[test]
+    fn test_srs_unkicked_rotation() {
+        let system = RotationSystem::Srs;
+
+        // T pointing up, box at (3, 0), rotates clockwise into column 4
+        let kicks = system.kicks(PieceKind::T, (3, 0, 2), 1);
+        assert_eq!(kicks[0], (4, 0, 3));
+        assert_eq!(kicks[1], (3, 0, 3));
+
+        // Horizontal I in row 1 of its box turns vertical in column 2
+        assert_eq!(system.kicks(PieceKind::I, (3, 1, 0), 1)[0], (5, 0, 1));
+    }
+}
@@ -0,0 +1,175 @@
+use std::{fmt, str::FromStr};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScoringSystem {
+    /// The fixed `line_scores` table.
+    Classic,
+    /// `line_scores` times the level plus one.
+    Nes,
+    Guideline,
+}
+
+pub const SCORING_SYSTEM_NAMES: [&str; 3] = ["classic", "nes", "guideline"];
+
+impl fmt::Display for ScoringSystem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            ScoringSystem::Classic => "classic",
+            ScoringSystem::Nes => "nes",
+            ScoringSystem::Guideline => "guideline",
+        };
+
+        write!(f, "{}", name)
+    }
+}
+
+impl FromStr for ScoringSystem {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "classic" => Ok(ScoringSystem::Classic),
+            "nes" => Ok(ScoringSystem::Nes),
+            "guideline" => Ok(ScoringSystem::Guideline),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Spin {
+    None,
+    Mini,
+    Full,
+}
+
+/// What a placement did, as far as scoring is concerned.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Clear {
+    pub lines: usize,
+    pub spin: Spin,
+    /// The board is empty after the clear.
+    pub perfect: bool,
+}
+
+impl Clear {
+    /// Tetrises and line clearing T-spins keep back-to-back chains going.
+    pub fn is_difficult(&self) -> bool {
+        self.lines == 4 || (self.lines > 0 && self.spin != Spin::None)
+    }
+}
+
+const GUIDELINE_LINES: [i64; 5] = [0, 100, 300, 500, 800];
+const GUIDELINE_MINI: [i64; 3] = [100, 200, 400];
+const GUIDELINE_SPIN: [i64; 4] = [400, 800, 1200, 1600];
+const GUIDELINE_PERFECT: [i64; 4] = [800, 1200, 1800, 2000];
+const GUIDELINE_PERFECT_B2B_TETRIS: i64 = 3200;
+const GUIDELINE_COMBO: i64 = 50;
+
+impl ScoringSystem {
+    /// Points for `clear` at `level`. `combo` counts the consecutive line
+    /// clears ending with this one, and `back_to_back` tells whether the
+    /// previous difficult clear is still unbroken.
+    pub fn points(
+        &self,
+        line_scores: &[i64; 4],
+        clear: &Clear,
+        level: usize,
+        combo: usize,
+        back_to_back: bool,
+    ) -> i64 {
+        let multiplier = level as i64 + 1;
+
+        match self {
+            ScoringSystem::Classic if clear.lines > 0 => line_scores[clear.lines - 1],
+            ScoringSystem::Nes if clear.lines > 0 => line_scores[clear.lines - 1] * multiplier,
+            ScoringSystem::Classic | ScoringSystem::Nes => 0,
+            ScoringSystem::Guideline => {
+                let b2b = back_to_back && clear.is_difficult();
+
+                let mut action = match clear.spin {
+                    Spin::None => GUIDELINE_LINES[clear.lines],
+                    Spin::Mini => GUIDELINE_MINI[clear.lines.min(2)],
+                    Spin::Full => GUIDELINE_SPIN[clear.lines.min(3)],
+                };
+                if b2b {
+                    action = action * 3 / 2;
+                }
+
+                let perfect = match clear.lines {
+                    4 if clear.perfect && b2b => GUIDELINE_PERFECT_B2B_TETRIS,
+                    1..=4 if clear.perfect => GUIDELINE_PERFECT[clear.lines - 1],
+                    _ => 0,
+                };
+
+                let combo = GUIDELINE_COMBO * combo.saturating_sub(1) as i64;
+
+                (action + perfect + combo) * multiplier
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LINE_SCORES: [i64; 4] = [40, 100, 300, 1200];
+
+    fn clear(lines: usize, spin: Spin) -> Clear {
+        Clear {
+            lines,
+            spin,
+            perfect: false,
+        }
+    }
+
+    #[test]
+    fn test_classic_and_nes() {
+        let tetris = clear(4, Spin::None);
+
+        assert_eq!(
+            ScoringSystem::Classic.points(&LINE_SCORES, &tetris, 5, 1, false),
+            1200
+        );
+        assert_eq!(
+            ScoringSystem::Nes.points(&LINE_SCORES, &tetris, 5, 1, false),
+            7200
+        );
+        assert_eq!(
+            ScoringSystem::Nes.points(&LINE_SCORES, &clear(0, Spin::None), 5, 0, false),
+            0
+        );
+    }
+
+    #[test]
+    fn test_guideline() {
+        let guideline = ScoringSystem::Guideline;
+        let points =
+            |clear: Clear, combo, b2b| guideline.points(&LINE_SCORES, &clear, 0, combo, b2b);
+
+        assert_eq!(points(clear(1, Spin::None), 1, false), 100);
+        assert_eq!(points(clear(0, Spin::Full), 0, false), 400);
+        assert_eq!(points(clear(2, Spin::Full), 1, false), 1200);
+        assert_eq!(points(clear(1, Spin::Mini), 1, false), 200);
+
+        // Back-to-back only rewards difficult clears
+        assert_eq!(points(clear(4, Spin::None), 1, true), 1200);
+        assert_eq!(points(clear(3, Spin::None), 1, true), 500);
+
+        // Third clear in a row
+        assert_eq!(points(clear(1, Spin::None), 3, false), 200);
+
+        let perfect = Clear {
+            perfect: true,
+            ..clear(4, Spin::None)
+        };
+        assert_eq!(points(perfect, 1, false), 2800);
+        assert_eq!(points(perfect, 1, true), 4400);
+
+        assert_eq!(
+            guideline.points(&LINE_SCORES, &clear(2, Spin::None), 2, 1, false),
+            900
+        );
+    }
+}
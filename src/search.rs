@@ -1,19 +1,49 @@
 use crate::{
-    net::FeedForwardNetwork,
-    pos::{Action, Position},
+    net::{FeedForwardNetwork, NetError},
+    options::Options,
+    pos::{Action, Color, Placement, Position, Rules},
+    timeline::Timeline,
     transposition::TranspositionTable,
 };
 
-const MAX_DEPTH: usize = 3;
+/// Score of a lost position, below anything the network evaluates to.
+const GAME_OVER_SCORE: f64 = -1e9;
+
+pub struct BestMove {
+    pub placement: Placement,
+    pub action_list: Vec<Action>,
+    /// Frame schedule of `action_list`, when timings are set.
+    pub timeline: Option<Timeline>,
+    /// Cells of the piece at its spawn, then after each action.
+    pub cells: Vec<[(i32, i32); 4]>,
+}
 
 pub struct Search {
     tt: TranspositionTable,
+    min_depth: usize,
+    max_depth: usize,
+    rules: Rules,
+    /// `rules` without timings, as checking every path in the tree is too
+    /// slow and only the root move gets played.
+    tree_rules: Rules,
 }
 
 impl Search {
-    pub fn new() -> Search {
+    pub fn new(options: &Options) -> Search {
+        Search::with_rules(options, options.rules())
+    }
+
+    /// A search playing by `rules` rather than the ones set in `options`.
+    pub fn with_rules(options: &Options, rules: Rules) -> Search {
         Search {
-            tt: TranspositionTable::new(16384),
+            tt: TranspositionTable::new(options.hash_size),
+            min_depth: options.min_depth.min(options.max_depth),
+            max_depth: options.max_depth,
+            rules,
+            tree_rules: Rules {
+                timing: None,
+                ..rules
+            },
         }
     }
 
@@ -21,18 +51,37 @@ impl Search {
         &mut self,
         net: &mut FeedForwardNetwork,
         pos: &Position,
-    ) -> Option<((usize, usize, usize), Vec<Action>)> {
+    ) -> Result<Option<BestMove>, NetError> {
+        let placements = pos.legal_moves(&self.rules).swap_remove(0);
+
+        // The tree only needs the bits of the board, not its colours
+        let root = pos.uncolored();
         let mut best_move = None;
-        for depth in 2..4 {
-            best_move = self.search_root(net, depth, pos);
+        for depth in self.min_depth..=self.max_depth {
+            best_move = self.search_root(net, depth, &root, &placements)?;
         }
 
-        let best_move = best_move?;
-
-        Some((
-            best_move,
-            pos.path((best_move.0 as i32, best_move.1 as i32, best_move.2 as i32)),
-        ))
+        let Some(placement) = best_move else {
+            return Ok(None);
+        };
+
+        let timed = self
+            .rules
+            .timing_at(pos.level)
+            .and_then(|timing| timing.schedule_placement(pos, &self.rules, placement));
+        let (action_list, timeline) = match timed {
+            Some((action_list, timeline)) => (action_list, Some(timeline)),
+            None => (pos.placement_path(&self.rules, placement), None),
+        };
+
+        let cells = pos.path_cells(&self.rules, placement, &action_list);
+
+        Ok(Some(BestMove {
+            placement,
+            action_list,
+            timeline,
+            cells,
+        }))
     }
 
     fn search_root(
@@ -40,27 +89,37 @@ impl Search {
         net: &mut FeedForwardNetwork,
         depth: usize,
         pos: &Position,
-    ) -> Option<(usize, usize, usize)> {
+        placements: &[Placement],
+    ) -> Result<Option<Placement>, NetError> {
         let mut maxscore = -f64::INFINITY;
         let mut best_move = None;
 
-        for &(p, x, y, rot) in pos.legal_moves()[0].iter() {
-            let pos = pos.apply_move(p, x, y, rot, false);
-            let score = self.search(net, pos, depth - 1);
+        for &placement in placements {
+            let pos = pos.apply_move(&self.rules, placement, None);
+            let score = self.search(net, pos, depth - 1)?;
 
             if score > maxscore {
                 maxscore = score;
-                best_move = Some((x, y, rot));
+                best_move = Some(placement);
             }
         }
 
-        best_move
+        Ok(best_move)
     }
 
-    fn search(&mut self, net: &mut FeedForwardNetwork, pos: Position, depth: usize) -> f64 {
+    fn search(
+        &mut self,
+        net: &mut FeedForwardNetwork,
+        pos: Position,
+        depth: usize,
+    ) -> Result<f64, NetError> {
+        if pos.is_game_over(&self.rules).is_some() {
+            return Ok(GAME_OVER_SCORE);
+        }
+
         if depth == 0 {
             if let Some(score) = self.tt.get(pos.hash) {
-                return score;
+                return Ok(score);
             } else {
                 let features = pos.features();
 
@@ -68,20 +127,16 @@ impl Search {
                 //     + features.holes * -0.35663
                 //     + features.bumpiness * -0.184483;
 
-                let score = net.activate(vec![
-                    features.holes,
-                    features.bumpiness,
-                    features.aggregate_height,
-                ])[0];
+                let score = net.activate(features.inputs())?[0];
 
                 self.tt.set(pos.hash, score);
 
-                return score;
+                return Ok(score);
             }
         }
 
         let mut maxscore = 0.;
-        let piece_list = pos.legal_moves();
+        let piece_list = pos.legal_moves(&self.tree_rules);
         for piece_moves in piece_list {
             if piece_moves.is_empty() {
                 continue;
@@ -89,26 +144,27 @@ impl Search {
 
             let mut piece_maxscore = -f64::INFINITY;
 
-            let piece_color = piece_moves[0].0;
+            let piece = piece_moves[0].dealt();
 
-            for (p, x, y, rot) in piece_moves {
-                let pos = pos.apply_move(p, x, y, rot, false);
-                let score = self.search(net, pos, depth - 1);
+            for placement in piece_moves {
+                let pos = pos.apply_move(&self.rules, placement, None);
+                let score = self.search(net, pos, depth - 1)?;
 
                 if score > piece_maxscore {
                     piece_maxscore = score;
                 }
             }
 
-            let prob = if pos.last_piece == piece_color {
-                3.57
+            // Unknown pieces form a chance node weighted by the randomizer
+            let weight = if pos.current_piece == Color::Random {
+                pos.randomizer.probability(piece.into())
             } else {
-                16.07
+                1.
             };
 
-            maxscore += piece_maxscore / prob;
+            maxscore += piece_maxscore * weight;
         }
 
-        maxscore
+        Ok(maxscore)
     }
 }
@@ -0,0 +1,439 @@
+use std::{cmp::Reverse, collections::BinaryHeap};
+
+use rustc_hash::FxHashSet;
+use serde::Serialize;
+
+use crate::{
+    piece::PieceKind,
+    pos::{Action, Placement, Position, Rules},
+    rotation::State,
+};
+
+/// Frame timings of the game the inputs are sent to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Timing {
+    /// Frames per row of gravity.
+    pub gravity: u32,
+    /// Frames a direction is held before auto-repeat starts.
+    pub das: u32,
+    /// Frames between auto-repeat moves, 0 moving to the wall at once.
+    pub arr: u32,
+    /// Minimum number of frames between two inputs.
+    pub input_interval: u32,
+    /// Frames a piece can rest on the stack before gravity locks it.
+    pub lock_delay: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct TimedAction {
+    pub frame: u32,
+    pub action: Action,
+    /// Frames the input is held down.
+    pub frames: u32,
+}
+
+pub type Timeline = Vec<TimedAction>;
+
+/// A piece in the schedule search, with the node it came from and the
+/// input sent on the way, if any.
+type SearchNode<'a> = (Falling<'a>, Option<(usize, Option<TimedAction>)>);
+
+/// A piece falling under gravity, advanced one frame at a time.
+#[derive(Clone, Copy)]
+struct Falling<'a> {
+    pos: &'a Position,
+    timing: Timing,
+    piece: PieceKind,
+    state: State,
+    frame: u32,
+    since_drop: u32,
+    resting: u32,
+}
+
+impl Falling<'_> {
+    fn moved(&self, (dx, dy): (i32, i32)) -> Option<State> {
+        let (x, y, rot) = self.state;
+        let state = (x + dx, y + dy, rot);
+        self.pos.fits(self.piece, state).then_some(state)
+    }
+
+    /// Advances one frame, returning false once the piece has locked.
+    fn tick(&mut self) -> bool {
+        self.frame += 1;
+        self.since_drop += 1;
+
+        let below = self.moved((0, 1));
+        if below.is_some() {
+            self.resting = 0;
+        } else {
+            self.resting += 1;
+        }
+
+        if self.since_drop >= self.timing.gravity {
+            match below {
+                Some(state) => {
+                    self.state = state;
+                    self.since_drop = 0;
+                }
+                None if self.resting > self.timing.lock_delay => return false,
+                None => {}
+            }
+        }
+
+        true
+    }
+
+    fn shift(&mut self, dx: i32) -> Option<()> {
+        self.state = self.moved((dx, 0))?;
+        Some(())
+    }
+}
+
+impl Timing {
+    /// The path to `placement` along with its schedule. When the path of
+    /// the rules can't be timed, the inputs are searched frame by frame.
+    pub fn schedule_placement(
+        &self,
+        pos: &Position,
+        rules: &Rules,
+        placement: Placement,
+    ) -> Option<(Vec<Action>, Timeline)> {
+        let path = pos.placement_path(rules, placement);
+        match self.schedule(pos, rules, placement, &path) {
+            Some(timeline) => Some((path, timeline)),
+            None => self.search(pos, rules, placement),
+        }
+    }
+
+    /// Searches, in frame order, for taps bringing the piece of `placement`
+    /// from its spawn to its resting place under gravity. Much slower than
+    /// scheduling a path, but finds tucks that only work with the right
+    /// timing.
+    fn search(
+        &self,
+        pos: &Position,
+        rules: &Rules,
+        placement: Placement,
+    ) -> Option<(Vec<Action>, Timeline)> {
+        const TAPS: [Action; 5] = [
+            Action::MoveLeft,
+            Action::MoveRight,
+            Action::RotateCounterclockwise,
+            Action::RotateClockwise,
+            Action::SoftDrop,
+        ];
+
+        let piece = placement.piece;
+        let goal = (placement.x as i32, placement.y as i32, placement.rot as i32);
+        let mut falling = Falling {
+            pos,
+            timing: *self,
+            piece,
+            state: pos.spawn(rules, piece),
+            frame: 0,
+            since_drop: 0,
+            resting: 0,
+        };
+
+        let mut prefix = Vec::new();
+        if placement.held.is_some() {
+            prefix.push(TimedAction {
+                frame: 0,
+                action: Action::Hold,
+                frames: 1,
+            });
+            while falling.frame < self.input_interval {
+                if !falling.tick() {
+                    return None;
+                }
+            }
+        }
+
+        // Each node is a moment an input can be sent, reached from its
+        // parent by waiting a frame or by an input. Gravity doesn't depend
+        // on the frame, which is left out of the seen set.
+        let mut nodes: Vec<SearchNode> = vec![(falling, None)];
+        let mut seen = FxHashSet::default();
+        let mut frontier = BinaryHeap::new();
+        frontier.push(Reverse((falling.frame, 0)));
+
+        let timed_path = |nodes: &[SearchNode], mut i: usize, last: Option<TimedAction>| {
+            let mut timeline: Timeline = last.into_iter().collect();
+            while let Some((parent, timed)) = nodes[i].1 {
+                timeline.extend(timed);
+                i = parent;
+            }
+            timeline.extend(prefix.iter().rev());
+            timeline.reverse();
+
+            let path = timeline.iter().map(|timed| timed.action).collect();
+            (path, timeline)
+        };
+
+        while let Some(Reverse((frame, i))) = frontier.pop() {
+            let falling = nodes[i].0;
+            if !seen.insert((falling.state, falling.since_drop, falling.resting)) {
+                continue;
+            }
+
+            if pos.slide(piece, falling.state, (0, 1)) == goal {
+                let hard_drop = TimedAction {
+                    frame,
+                    action: Action::HardDrop,
+                    frames: 1,
+                };
+                return Some(timed_path(&nodes, i, Some(hard_drop)));
+            }
+
+            let mut waited = falling;
+            if waited.tick() {
+                frontier.push(Reverse((waited.frame, nodes.len())));
+                nodes.push((waited, Some((i, None))));
+            } else if waited.state == goal {
+                return Some(timed_path(&nodes, i, None));
+            }
+
+            for action in TAPS {
+                let mut next = falling;
+                let moved = match action {
+                    Action::MoveLeft => next.shift(-1),
+                    Action::MoveRight => next.shift(1),
+                    Action::SoftDrop => next.moved((0, 1)).map(|state| {
+                        next.state = state;
+                        next.since_drop = 0;
+                    }),
+                    _ => {
+                        let dir = if action == Action::RotateClockwise {
+                            1
+                        } else {
+                            -1
+                        };
+                        pos.rotate(rules, piece, next.state, dir)
+                            .map(|state| next.state = state)
+                    }
+                };
+                if moved.is_none() {
+                    continue;
+                }
+
+                let timed = TimedAction {
+                    frame,
+                    action,
+                    frames: 1,
+                };
+                let mut locked = false;
+                while !locked && next.frame < frame + self.input_interval {
+                    locked = !next.tick();
+                }
+
+                if !locked {
+                    frontier.push(Reverse((next.frame, nodes.len())));
+                    nodes.push((next, Some((i, Some(timed)))));
+                } else if next.state == goal {
+                    return Some(timed_path(&nodes, i, Some(timed)));
+                }
+            }
+        }
+
+        None
+    }
+
+    /// Schedules `path` frame by frame from the spawn of the piece of
+    /// `placement`, or returns `None` if gravity gets in the way or the
+    /// piece locks before reaching `placement`.
+    pub fn schedule(
+        &self,
+        pos: &Position,
+        rules: &Rules,
+        placement: Placement,
+        path: &[Action],
+    ) -> Option<Timeline> {
+        let piece = placement.piece;
+        let goal = (placement.x as i32, placement.y as i32, placement.rot as i32);
+        let spawn = pos.spawn(rules, piece);
+
+        let mut falling = Falling {
+            pos,
+            timing: *self,
+            piece,
+            state: spawn,
+            frame: 0,
+            since_drop: 0,
+            resting: 0,
+        };
+        // Where the path expects the piece to be without gravity
+        let mut planned = spawn;
+        let mut next_input = 0;
+        let mut timeline = Vec::new();
+
+        for &action in path {
+            // Gravity may already have done the planned soft drops
+            if action == Action::SoftDrop {
+                planned.1 += 1;
+                if falling.state.1 >= planned.1 {
+                    continue;
+                }
+            }
+
+            while falling.frame < next_input {
+                if !falling.tick() {
+                    return None;
+                }
+            }
+
+            let frame = falling.frame;
+
+            match action {
+                Action::MoveLeft | Action::MoveRight => {
+                    let dx = if action == Action::MoveLeft { -1 } else { 1 };
+                    planned.0 += dx;
+                    falling.shift(dx)?;
+                }
+                Action::SoftDrop => {
+                    falling.state = falling.moved((0, 1))?;
+                    falling.since_drop = 0;
+                }
+                Action::HardDrop => {
+                    timeline.push(TimedAction {
+                        frame,
+                        action,
+                        frames: 1,
+                    });
+                    let landing = pos.slide(piece, falling.state, (0, 1));
+                    return (landing == goal).then_some(timeline);
+                }
+                Action::DasLeft | Action::DasRight => {
+                    let dx = if action == Action::DasLeft { -1 } else { 1 };
+                    planned = pos.slide(piece, planned, (dx, 0));
+                    falling.shift(dx)?;
+
+                    let mut held = 0;
+                    while falling.moved((dx, 0)).is_some() {
+                        if !falling.tick() {
+                            return None;
+                        }
+                        held += 1;
+
+                        if held < self.das {
+                            continue;
+                        }
+
+                        if self.arr == 0 {
+                            falling.state = pos.slide(piece, falling.state, (dx, 0));
+                        } else if (held - self.das).is_multiple_of(self.arr) {
+                            falling.shift(dx)?;
+                        }
+                    }
+
+                    timeline.push(TimedAction {
+                        frame,
+                        action,
+                        frames: held + 1,
+                    });
+                    next_input = falling.frame + self.input_interval;
+                    continue;
+                }
+                Action::RotateCounterclockwise | Action::RotateClockwise => {
+                    let dir = if action == Action::RotateClockwise {
+                        1
+                    } else {
+                        -1
+                    };
+                    planned = pos.rotate(rules, piece, planned, dir).unwrap_or(planned);
+                    falling.state = pos.rotate(rules, piece, falling.state, dir)?;
+                }
+                Action::Hold | Action::None => {}
+            }
+
+            timeline.push(TimedAction {
+                frame,
+                action,
+                frames: 1,
+            });
+            next_input = frame + self.input_interval;
+        }
+
+        // Without a hard drop the piece locks where gravity leaves it
+        while falling.tick() {}
+
+        (falling.state == goal).then_some(timeline)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    const NES: Timing = Timing {
+        gravity: 48,
+        das: 16,
+        arr: 6,
+        input_interval: 2,
+        lock_delay: 0,
+    };
+
+    #[test]
+    fn test_schedule_taps() {
+        let pos = Position::default();
+        let rules = Rules::default();
+        let placement = Placement::new(PieceKind::I, 0, 21, 0);
+        let path = pos.placement_path(&rules, placement);
+
+        let timeline = NES.schedule(&pos, &rules, placement, &path).unwrap();
+        let frames: Vec<u32> = timeline.iter().map(|timed| timed.frame).collect();
+        assert_eq!(frames, [0, 2, 4, 6]);
+        assert_eq!(timeline.last().unwrap().action, Action::HardDrop);
+    }
+
+    #[test]
+    fn test_schedule_das() {
+        let pos = Position::default();
+        let rules = Rules {
+            finesse: true,
+            das: true,
+            ..Rules::default()
+        };
+        let placement = Placement::new(PieceKind::I, 6, 21, 0);
+        let path = pos.placement_path(&rules, placement);
+        assert_eq!(path, [Action::DasRight, Action::HardDrop]);
+
+        // One move at once, then two more after 16 and 22 frames
+        let timeline = NES.schedule(&pos, &rules, placement, &path).unwrap();
+        assert_eq!(timeline[0].frames, 23);
+        assert_eq!(timeline[1].frame, 24);
+    }
+
+    #[test]
+    fn test_schedule_too_slow() {
+        let rules = Rules::default();
+        let pos = Position::from_str("////////////////////ZZZZ6// I J 0 -").unwrap();
+        let tuck = Placement::new(PieceKind::I, 0, 21, 0);
+        let path = pos.placement_path(&rules, tuck);
+
+        assert!(NES.schedule(&pos, &rules, tuck, &path).is_some());
+
+        // At a row per frame the piece lands and locks before it can slide under
+        let fast = Timing { gravity: 1, ..NES };
+        assert!(fast.schedule(&pos, &rules, tuck, &path).is_none());
+        assert!(fast.schedule_placement(&pos, &rules, tuck).is_none());
+
+        // Tucking one cell under still works by dropping before sliding,
+        // which the shortest path doesn't do
+        let slide = Placement::new(PieceKind::I, 3, 21, 0);
+        let path = pos.placement_path(&rules, slide);
+        assert!(fast.schedule(&pos, &rules, slide, &path).is_none());
+
+        let (path, timeline) = fast.schedule_placement(&pos, &rules, slide).unwrap();
+        assert_eq!(path.first(), Some(&Action::MoveRight));
+        assert_eq!(path.last(), Some(&Action::MoveLeft));
+        assert_eq!(timeline.len(), path.len());
+
+        let timed = Rules {
+            timing: Some(fast),
+            ..rules
+        };
+        let moves = pos.legal_moves(&timed).swap_remove(0);
+        assert!(moves.contains(&slide) && !moves.contains(&tuck));
+    }
+}
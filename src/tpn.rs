@@ -0,0 +1,766 @@
+//! Tetris Position Notation, a position as one line of space separated
+//! fields:
+//!
+//! ```text
+//! v2 board current queue score hold combo b2b lines level garbage sent randomizer
+//! ```
+//!
+//! Version 1, without the leading `v2`, stops at the board, current piece,
+//! queue and score, any later field being optional and the randomizer
+//! state being guessed from the known pieces. It is still read, while
+//! positions are always written as version 2.
+
+use std::{fmt, str::FromStr};
+
+use crate::{
+    garbage::{Garbage, GarbageQueue},
+    pos::{
+        hash_board, BitBoard, Board, Cell, Color, Position, Queue, BOARD_HEIGHT, BOARD_WIDTH,
+        MAX_PREVIEW,
+    },
+    randomizer::Randomizer,
+};
+
+/// Version of the TPN strings written by `Position`.
+pub const TPN_VERSION: u32 = 2;
+
+/// Largest score either way a position can hold, far past any game while
+/// leaving room for the points of later clears.
+const MAX_SCORE: i64 = 1 << 53;
+/// Largest combo a position can hold.
+const MAX_COMBO: usize = 1_000_000;
+/// Largest line count, level and garbage sent a position can hold, which
+/// the scoring multiplies with the combo without overflowing.
+const MAX_LINES: usize = 1_000_000;
+const MAX_LEVEL: usize = 1_000_000;
+const MAX_GARBAGE_SENT: usize = 1_000_000;
+
+/// Why a TPN string doesn't parse, with the byte offset of the problem.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TpnError {
+    /// The `WxH:` prefix is malformed or not a supported size.
+    BadSize {
+        offset: usize,
+    },
+    /// A row has more cells than the board is wide.
+    RowOverflow {
+        offset: usize,
+    },
+    /// The board goes on past its last row.
+    TooManyRows {
+        offset: usize,
+    },
+    /// The board ends before its last row.
+    TooFewRows {
+        offset: usize,
+    },
+    UnknownPiece {
+        offset: usize,
+        found: char,
+    },
+    MissingField {
+        offset: usize,
+        field: &'static str,
+    },
+    BadScore {
+        offset: usize,
+    },
+    /// Any other field that doesn't hold a valid value.
+    BadField {
+        offset: usize,
+        field: &'static str,
+    },
+}
+
+impl TpnError {
+    pub fn offset(&self) -> usize {
+        match *self {
+            TpnError::BadSize { offset }
+            | TpnError::RowOverflow { offset }
+            | TpnError::TooManyRows { offset }
+            | TpnError::TooFewRows { offset }
+            | TpnError::UnknownPiece { offset, .. }
+            | TpnError::MissingField { offset, .. }
+            | TpnError::BadScore { offset }
+            | TpnError::BadField { offset, .. } => offset,
+        }
+    }
+}
+
+impl fmt::Display for TpnError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TpnError::BadSize { .. } => write!(f, "Invalid board size")?,
+            TpnError::RowOverflow { .. } => write!(f, "Row too long")?,
+            TpnError::TooManyRows { .. } => write!(f, "Too many rows")?,
+            TpnError::TooFewRows { .. } => write!(f, "Missing rows")?,
+            TpnError::UnknownPiece { found, .. } => write!(f, "Unknown piece {:?}", found)?,
+            TpnError::MissingField { field, .. } => write!(f, "Missing {}", field)?,
+            TpnError::BadScore { .. } => write!(f, "Invalid score")?,
+            TpnError::BadField { field, .. } => write!(f, "Invalid {}", field)?,
+        }
+
+        write!(f, " at byte {}", self.offset())
+    }
+}
+
+impl fmt::Display for Position {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "v{} ", TPN_VERSION)?;
+
+        let (width, height) = (self.width(), self.height());
+        if (width, height) != (BOARD_WIDTH, BOARD_HEIGHT) {
+            write!(f, "{}x{}:", width, height)?;
+        }
+
+        // Runs of empty cells as digits adding up to their length, leaving
+        // empty rows blank
+        let write_empty = |f: &mut fmt::Formatter<'_>, mut empty_cells: usize| {
+            while empty_cells > 0 {
+                write!(f, "{}", empty_cells.min(9))?;
+                empty_cells -= empty_cells.min(9);
+            }
+            Ok(())
+        };
+
+        for row in self.board().rows() {
+            if row.iter().any(|cell| !cell.is_empty()) {
+                let mut empty_cells = 0;
+                for &cell in row {
+                    if cell.is_empty() {
+                        empty_cells += 1;
+                    } else {
+                        write_empty(f, empty_cells)?;
+                        empty_cells = 0;
+                        write!(f, "{}", cell)?;
+                    }
+                }
+                write_empty(f, empty_cells)?;
+            }
+
+            write!(f, "/")?;
+        }
+
+        write!(f, " {} ", self.current_piece)?;
+
+        if self.queue.is_empty() {
+            write!(f, "-")?;
+        }
+
+        for piece in self.queue.iter() {
+            write!(f, "{}", piece)?;
+        }
+
+        write!(f, " {} ", self.score)?;
+
+        // Hold slot, '-' when empty and marked with '*' once used this turn
+        match self.hold_piece {
+            Color::Empty => write!(f, "-")?,
+            hold_piece => write!(f, "{}", hold_piece)?,
+        }
+
+        if self.hold_used {
+            write!(f, "*")?;
+        }
+
+        write!(f, " {} {}", self.combo, self.back_to_back as u8)?;
+        write!(f, " {} {}", self.lines, self.level)?;
+
+        write!(f, " ")?;
+        if self.pending_garbage.is_empty() {
+            write!(f, "-")?;
+        }
+        for (i, garbage) in self.pending_garbage.iter().enumerate() {
+            if i > 0 {
+                write!(f, ",")?;
+            }
+            write!(f, "{}", garbage)?;
+        }
+        write!(f, " {}", self.garbage_sent)?;
+
+        write!(f, " {}", self.randomizer)
+    }
+}
+
+/// A piece of the current piece or the queue, `?` standing for an unknown
+/// one.
+fn parse_piece(c: char, offset: usize) -> Result<Color, TpnError> {
+    match Color::try_from(c) {
+        Ok(Color::Garbage) | Err(()) => Err(TpnError::UnknownPiece { offset, found: c }),
+        Ok(piece) => Ok(piece),
+    }
+}
+
+/// A number from 0 up to `max`.
+fn parse_number<T: FromStr + PartialOrd>(
+    (offset, tok): (usize, &str),
+    field: &'static str,
+    max: T,
+) -> Result<T, TpnError> {
+    tok.parse()
+        .ok()
+        .filter(|n| *n <= max)
+        .ok_or(TpnError::BadField { offset, field })
+}
+
+impl FromStr for Position {
+    type Err = TpnError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // Fields with their byte offsets
+        let mut offset = 0;
+        let mut fields: Vec<(usize, &str)> = s
+            .split(' ')
+            .map(|tok| {
+                let field = (offset, tok);
+                offset += tok.len() + 1;
+                field
+            })
+            .collect();
+
+        let version = match fields[0] {
+            (_, "v2") => 2,
+            (offset, tok) if tok.starts_with('v') => {
+                return Err(TpnError::BadField {
+                    offset,
+                    field: "version",
+                })
+            }
+            _ => 1,
+        };
+        if version > 1 {
+            fields.remove(0);
+        }
+
+        let required = |i: usize, field| {
+            fields.get(i).copied().ok_or(TpnError::MissingField {
+                offset: s.len(),
+                field,
+            })
+        };
+        // Fields after the score can be left out before version 2
+        let optional = |i: usize, field| match version {
+            1 => Ok(fields.get(i).copied()),
+            _ => required(i, field).map(Some),
+        };
+
+        // Boards other than 10x22 start with their size, as in `10x40:`
+        let (offset, board_tok) = required(0, "board")?;
+        let bad_size = TpnError::BadSize { offset };
+        let (width, height, offset, board_tok) = match board_tok.split_once(':') {
+            Some((size, board_tok)) => {
+                let (width, height) = size.split_once('x').ok_or(bad_size)?;
+                let width = width.parse().map_err(|_| bad_size)?;
+                let height = height.parse().map_err(|_| bad_size)?;
+                (width, height, offset + size.len() + 1, board_tok)
+            }
+            None => (BOARD_WIDTH, BOARD_HEIGHT, offset, board_tok),
+        };
+        if !Board::<Color>::is_valid_size(width, height) {
+            return Err(bad_size);
+        }
+
+        // Every row ends with a `/`, runs of empty cells being digits
+        let mut board = Board::new(width, height, Color::Empty);
+        let (mut x, mut y) = (0, 0);
+        for (i, c) in board_tok.char_indices() {
+            let offset = offset + i;
+            if y == height {
+                return Err(TpnError::TooManyRows { offset });
+            }
+
+            match c {
+                '/' => {
+                    x = 0;
+                    y += 1;
+                }
+                '1'..='9' => {
+                    x += c as usize - '0' as usize;
+                    if x > width {
+                        return Err(TpnError::RowOverflow { offset });
+                    }
+                }
+                _ => {
+                    let cell = match Color::try_from(c) {
+                        Ok(Color::Random) | Err(()) => {
+                            return Err(TpnError::UnknownPiece { offset, found: c })
+                        }
+                        Ok(cell) => cell,
+                    };
+                    if x == width {
+                        return Err(TpnError::RowOverflow { offset });
+                    }
+                    board[y][x] = cell;
+                    x += 1;
+                }
+            }
+        }
+        if y < height {
+            return Err(TpnError::TooFewRows {
+                offset: offset + board_tok.len(),
+            });
+        }
+
+        let (offset, current_tok) = required(1, "current piece")?;
+        let mut chars = current_tok.chars();
+        let current_piece = match (chars.next(), chars.next()) {
+            (Some(c), None) => parse_piece(c, offset)?,
+            _ => {
+                return Err(TpnError::BadField {
+                    offset,
+                    field: "current piece",
+                })
+            }
+        };
+
+        let (offset, queue_tok) = required(2, "queue")?;
+        let queue: Queue = match queue_tok {
+            "-" => Queue::new(),
+            _ if queue_tok.is_empty() || queue_tok.len() > MAX_PREVIEW => {
+                return Err(TpnError::BadField {
+                    offset,
+                    field: "queue",
+                })
+            }
+            _ => queue_tok
+                .char_indices()
+                .map(|(i, c)| parse_piece(c, offset + i))
+                .collect::<Result<_, _>>()?,
+        };
+
+        let (offset, score_tok) = required(3, "score")?;
+        let score = score_tok
+            .parse()
+            .ok()
+            .filter(|score| (-MAX_SCORE..=MAX_SCORE).contains(score))
+            .ok_or(TpnError::BadScore { offset })?;
+
+        // Hold slot, '-' when empty and marked with '*' once used this turn
+        let (hold_piece, hold_used) = match optional(4, "hold piece")? {
+            Some((offset, hold_tok)) => {
+                let bad_hold = TpnError::BadField {
+                    offset,
+                    field: "hold piece",
+                };
+                let hold_used = hold_tok.ends_with('*');
+                let mut chars = hold_tok.trim_end_matches('*').chars();
+                match (chars.next(), chars.next()) {
+                    (Some('-'), None) => (Color::Empty, hold_used),
+                    (Some('?'), None) => return Err(bad_hold),
+                    (Some(c), None) => (parse_piece(c, offset)?, hold_used),
+                    _ => return Err(bad_hold),
+                }
+            }
+            None => (Color::Empty, false),
+        };
+
+        let combo = match optional(5, "combo")? {
+            Some(field) => parse_number(field, "combo", MAX_COMBO)?,
+            None => 0,
+        };
+        let back_to_back = match optional(6, "back-to-back")? {
+            Some((_, "0")) | None => false,
+            Some((_, "1")) => true,
+            Some((offset, _)) => {
+                return Err(TpnError::BadField {
+                    offset,
+                    field: "back-to-back",
+                })
+            }
+        };
+
+        let lines = match optional(7, "lines")? {
+            Some(field) => parse_number(field, "lines", MAX_LINES)?,
+            None => 0,
+        };
+        let level = match optional(8, "level")? {
+            Some(field) => parse_number(field, "level", MAX_LEVEL)?,
+            None => 0,
+        };
+
+        let mut pending_garbage = GarbageQueue::new();
+        match optional(9, "garbage")? {
+            Some((_, "-")) | None => {}
+            Some((mut offset, garbage_tok)) => {
+                for garbage_tok in garbage_tok.split(',') {
+                    let bad_garbage = TpnError::BadField {
+                        offset,
+                        field: "garbage",
+                    };
+                    let garbage = Garbage::from_str(garbage_tok).map_err(|_| bad_garbage)?;
+                    if garbage.hole >= width
+                        || garbage.lines == 0
+                        || garbage.lines > height
+                        || pending_garbage.try_push(garbage).is_err()
+                    {
+                        return Err(bad_garbage);
+                    }
+                    offset += garbage_tok.len() + 1;
+                }
+            }
+        }
+        let garbage_sent = match optional(10, "garbage sent")? {
+            Some(field) => parse_number(field, "garbage sent", MAX_GARBAGE_SENT)?,
+            None => 0,
+        };
+
+        let randomizer = match optional(11, "randomizer")? {
+            Some((offset, randomizer_tok)) => Some(Randomizer::from_str(randomizer_tok).map_err(
+                |_| TpnError::BadField {
+                    offset,
+                    field: "randomizer",
+                },
+            )?),
+            None => None,
+        };
+
+        if let Some(&(offset, _)) = fields.get(12) {
+            return Err(TpnError::BadField {
+                offset,
+                field: "end of position",
+            });
+        }
+
+        let hash = hash_board(&BitBoard::new(&board));
+        let mut pos = Position::new(current_piece, queue, score, board, hash);
+        pos.lines = lines;
+        pos.level = level;
+        pos.hold_piece = hold_piece;
+        pos.hold_used = hold_used;
+        pos.combo = combo;
+        pos.back_to_back = back_to_back;
+        pos.pending_garbage = pending_garbage;
+        pos.garbage_sent = garbage_sent;
+        if let Some(randomizer) = randomizer {
+            pos.randomizer = randomizer;
+        }
+
+        Ok(pos)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        garbage::MAX_PENDING,
+        pos::{Rules, MAX_HEIGHT, MAX_WIDTH, MIN_SIZE},
+        scoring::ScoringSystem,
+    };
+    use rand::{rngs::SmallRng, seq::SliceRandom, Rng, SeedableRng};
+
+    const CELLS: [Color; 8] = [
+        Color::I,
+        Color::O,
+        Color::J,
+        Color::L,
+        Color::S,
+        Color::T,
+        Color::Z,
+        Color::Garbage,
+    ];
+
+    fn random_position(rng: &mut SmallRng) -> Position {
+        let width = rng.gen_range(MIN_SIZE..=MAX_WIDTH);
+        let height = rng.gen_range(MIN_SIZE..=MAX_HEIGHT);
+        let mut board = Board::new(width, height, Color::Empty);
+        let stack = rng.gen_range(0..=height);
+        for y in height - stack..height {
+            for x in 0..width {
+                if rng.gen_bool(0.6) {
+                    board[y][x] = *CELLS.choose(rng).unwrap();
+                }
+            }
+        }
+
+        let piece = |rng: &mut SmallRng| *CELLS[..7].choose(rng).unwrap();
+        let queue = (0..rng.gen_range(0..=MAX_PREVIEW))
+            .map(|_| {
+                if rng.gen_bool(0.2) {
+                    Color::Random
+                } else {
+                    piece(rng)
+                }
+            })
+            .collect();
+
+        let hash = hash_board(&BitBoard::new(&board));
+        // Mostly small counters, now and then the largest ones allowed
+        let counter = |rng: &mut SmallRng, small, max| {
+            if rng.gen_bool(0.1) {
+                max
+            } else {
+                rng.gen_range(0..small)
+            }
+        };
+        let score = match rng.gen_range(0..10) {
+            0 => -MAX_SCORE,
+            1 => MAX_SCORE,
+            _ => rng.gen_range(-10..1 << 40),
+        };
+        let mut pos = Position::new(piece(rng), queue, score, board, hash);
+        if rng.gen_bool(0.5) {
+            pos.hold_piece = piece(rng);
+        }
+        pos.hold_used = rng.gen();
+        pos.combo = counter(rng, 20, MAX_COMBO);
+        pos.back_to_back = rng.gen();
+        pos.lines = counter(rng, 1000, MAX_LINES);
+        pos.level = counter(rng, 30, MAX_LEVEL);
+        for _ in 0..rng.gen_range(0..=MAX_PENDING) {
+            pos.pending_garbage.push(Garbage {
+                lines: rng.gen_range(1..=height.min(7)),
+                hole: rng.gen_range(0..width),
+            });
+        }
+        pos.garbage_sent = counter(rng, 100, MAX_GARBAGE_SENT);
+        let kind = ["nes", "bag", "uniform", "tgm"].choose(rng).unwrap();
+        pos.randomizer = Randomizer::new(kind.parse().unwrap());
+        for _ in 0..rng.gen_range(0..10) {
+            pos.randomizer.deal(rng);
+        }
+
+        pos
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let mut rng = SmallRng::seed_from_u64(22);
+
+        for _ in 0..500 {
+            let pos = random_position(&mut rng);
+            let tpn = pos.to_string();
+            let parsed = Position::from_str(&tpn).unwrap();
+
+            assert_eq!(parsed.to_string(), tpn);
+            assert_eq!(parsed.board(), pos.board());
+            assert_eq!(parsed.bits, pos.bits);
+            assert_eq!(parsed.hash, pos.hash);
+            assert_eq!(parsed.queue, pos.queue);
+            assert_eq!(parsed.pending_garbage, pos.pending_garbage);
+            assert_eq!(parsed.randomizer, pos.randomizer);
+        }
+    }
+
+    #[test]
+    fn test_versions() {
+        let empty = "/".repeat(22);
+
+        // Version 1 leaves out the later fields and the randomizer
+        let v1 = Position::from_str(&format!("{} T SZ 0 I* 2 1", empty)).unwrap();
+        assert_eq!(
+            (v1.hold_piece, v1.combo, v1.back_to_back),
+            (Color::I, 2, true)
+        );
+        assert_eq!(v1.randomizer, Randomizer::Nes { last: Color::Z });
+        assert_eq!(
+            v1.to_string(),
+            format!("v2 {} T SZ 0 I* 2 1 0 0 - 0 nes:Z", empty)
+        );
+
+        let v2 = format!("v2 {} T SZ 0 - 0 0 0 0 - 0 bag:IOJL", empty);
+        let pos = Position::from_str(&v2).unwrap();
+        assert_eq!(pos.randomizer, Randomizer::Bag { remaining: 0b1111 });
+        assert_eq!(pos.to_string(), v2);
+
+        assert_eq!(
+            Position::from_str(&format!("v2 {} T SZ 0 - 0 0 0 0", empty)).err(),
+            Some(TpnError::MissingField {
+                offset: 42,
+                field: "garbage"
+            })
+        );
+        assert_eq!(
+            Position::from_str(&format!("v2 {} T SZ 0 - 0 0 0 0 - 0 bag:II", empty)).err(),
+            Some(TpnError::BadField {
+                offset: 47,
+                field: "randomizer"
+            })
+        );
+        assert_eq!(
+            Position::from_str(&format!("v3 {} T SZ 0", empty)).err(),
+            Some(TpnError::BadField {
+                offset: 0,
+                field: "version"
+            })
+        );
+    }
+
+    #[test]
+    fn test_limits() {
+        // Clearing a line from the largest values a position holds
+        let tpn = format!(
+            "{}ZZZZZZZZZ1/ I - {} - {} 1 {} {} - {}",
+            "/".repeat(21),
+            MAX_SCORE,
+            MAX_COMBO,
+            MAX_LINES,
+            MAX_LEVEL,
+            MAX_GARBAGE_SENT
+        );
+        let pos = Position::from_str(&tpn).unwrap();
+        for scoring in [
+            ScoringSystem::Classic,
+            ScoringSystem::Nes,
+            ScoringSystem::Guideline,
+        ] {
+            let rules = Rules {
+                scoring,
+                garbage: true,
+                ..Rules::default()
+            };
+            let next = pos.legal_moves(&rules)[0]
+                .iter()
+                .map(|&placement| pos.apply_move(&rules, placement, None))
+                .find(|next| next.lines > MAX_LINES)
+                .unwrap();
+            assert_eq!(next.combo, MAX_COMBO + 1);
+            assert!(next.score > MAX_SCORE);
+            assert!(next.level >= MAX_LEVEL);
+            assert!(next.garbage_sent > MAX_GARBAGE_SENT);
+        }
+
+        let empty = "/".repeat(22);
+        assert_eq!(
+            Position::from_str(&format!("{} I J {} - 0 0", empty, MAX_SCORE + 1)).err(),
+            Some(TpnError::BadScore { offset: 27 })
+        );
+        assert_eq!(
+            Position::from_str(&format!("{} I J {} - 0 0", empty, i64::MIN)).err(),
+            Some(TpnError::BadScore { offset: 27 })
+        );
+        assert_eq!(
+            Position::from_str(&format!("{} I J 0 - {} 0", empty, MAX_COMBO + 1)).err(),
+            Some(TpnError::BadField {
+                offset: 31,
+                field: "combo"
+            })
+        );
+        for (counters, offset, field) in [
+            ("1000001 0 - 0", 38, "lines"),
+            ("0 1000001 - 0", 40, "level"),
+            ("0 0 - 1000001", 44, "garbage sent"),
+        ] {
+            assert_eq!(
+                Position::from_str(&format!("v2 {} I J 0 - 0 0 {}", empty, counters)).err(),
+                Some(TpnError::BadField { offset, field })
+            );
+        }
+    }
+
+    #[test]
+    fn test_mutations_never_panic() {
+        let mut rng = SmallRng::seed_from_u64(23);
+        let alphabet: Vec<char> = "IOJLSTZG?-*/@:,x 0123456789".chars().collect();
+
+        for _ in 0..2000 {
+            let mut tpn: Vec<char> = random_position(&mut rng).to_string().chars().collect();
+            for _ in 0..rng.gen_range(1..4) {
+                let i = rng.gen_range(0..tpn.len());
+                match rng.gen_range(0..3) {
+                    0 => tpn[i] = *alphabet.choose(&mut rng).unwrap(),
+                    1 => tpn.insert(i, *alphabet.choose(&mut rng).unwrap()),
+                    _ => drop(tpn.remove(i)),
+                }
+            }
+            let tpn: String = tpn.into_iter().collect();
+
+            if let Err(err) = Position::from_str(&tpn) {
+                assert!(err.offset() <= tpn.len(), "{} in {:?}", err, tpn);
+            }
+        }
+
+        // Nothing may follow the randomizer
+        let tpn = random_position(&mut rng).to_string();
+        for extra in [" ", " 0", " bag:I"] {
+            assert_eq!(
+                Position::from_str(&format!("{}{}", tpn, extra)).err(),
+                Some(TpnError::BadField {
+                    offset: tpn.len() + 1,
+                    field: "end of position"
+                })
+            );
+        }
+    }
+
+    #[test]
+    fn test_errors() {
+        let empty = "/".repeat(22);
+        let parse = |s: &str| Position::from_str(s).err();
+
+        assert_eq!(
+            parse(&format!("ZZZZZZZZZZZ{} I J 0", empty)),
+            Some(TpnError::RowOverflow { offset: 10 })
+        );
+        assert_eq!(
+            parse(&format!("{}92{} I J 0", "/".repeat(3), &empty[3..])),
+            Some(TpnError::RowOverflow { offset: 4 })
+        );
+        assert_eq!(
+            parse(&format!("{}/ I J 0", empty)),
+            Some(TpnError::TooManyRows { offset: 22 })
+        );
+        assert_eq!(
+            parse(&format!("4x4:{} I J 0", &empty[..3])),
+            Some(TpnError::TooFewRows { offset: 7 })
+        );
+        assert_eq!(
+            parse(&format!("{} X J 0", empty)),
+            Some(TpnError::UnknownPiece {
+                offset: 23,
+                found: 'X'
+            })
+        );
+        assert_eq!(
+            parse(&format!("{} I JG 0", empty)),
+            Some(TpnError::UnknownPiece {
+                offset: 26,
+                found: 'G'
+            })
+        );
+        assert_eq!(
+            parse(&format!("{} I J", empty)),
+            Some(TpnError::MissingField {
+                offset: 26,
+                field: "score"
+            })
+        );
+        assert_eq!(
+            parse(&format!("{} I J 1e3", empty)),
+            Some(TpnError::BadScore { offset: 27 })
+        );
+        assert_eq!(
+            parse(&format!("{} I J 0 ? 0", empty)),
+            Some(TpnError::BadField {
+                offset: 29,
+                field: "hold piece"
+            })
+        );
+        assert_eq!(
+            parse(&format!("{} I J 0 - 0 0 0 0 1@2,2@10", empty)),
+            Some(TpnError::BadField {
+                offset: 43,
+                field: "garbage"
+            })
+        );
+        assert_eq!(
+            parse(&format!("{} I J 0 - 0 0 0 0 1@2,23@1", empty)),
+            Some(TpnError::BadField {
+                offset: 43,
+                field: "garbage"
+            })
+        );
+        assert_eq!(
+            parse(&format!("{} I J 0 - 0 0 0 0 1@2,0@1", empty)),
+            Some(TpnError::BadField {
+                offset: 43,
+                field: "garbage"
+            })
+        );
+        assert_eq!(
+            parse("11x22:/ I J 0").map(|err| err.to_string()),
+            Some("Missing rows at byte 7".to_string())
+        );
+        assert_eq!(parse("3x22:/ I J 0"), Some(TpnError::BadSize { offset: 0 }));
+    }
+}
@@ -0,0 +1,292 @@
+use rand::{rngs::SmallRng, Rng, SeedableRng};
+use serde::Serialize;
+
+use crate::{
+    game::{self, Game, GameSummary, Stats},
+    garbage::Garbage,
+    net::{FeedForwardNetwork, NetError},
+    options::Options,
+    pos::{Position, Rules},
+    search::Search,
+};
+
+/// Mixed into the seed of a match for the garbage holes, so that they
+/// don't follow the pieces.
+const HOLE_SEED: u64 = 0x6A09E667F3BCC908;
+
+/// Two games dealt the same pieces, trading garbage after every round in
+/// which each player places one piece.
+pub struct Versus {
+    pub players: [Game; 2],
+    /// Holes of the garbage received by each player. Both start from the
+    /// same seed, so equal attacks get equal holes.
+    holes: [SmallRng; 2],
+}
+
+impl Versus {
+    pub fn new(start: Position, seed: u64) -> Self {
+        let holes = SmallRng::seed_from_u64(seed ^ HOLE_SEED);
+
+        Versus {
+            players: [Game::new(start.clone(), seed), Game::new(start, seed)],
+            holes: [holes.clone(), holes],
+        }
+    }
+
+    pub fn set_preview(&mut self, len: usize) {
+        for game in self.players.iter_mut() {
+            game.set_preview(len);
+        }
+    }
+
+    /// Plays one round, returning false once a player's game is over.
+    pub fn round(
+        &mut self,
+        searches: &mut [Search; 2],
+        nets: &mut [FeedForwardNetwork; 2],
+        rules: &Rules,
+    ) -> Result<bool, NetError> {
+        let mut over = false;
+        let mut sent = [0; 2];
+
+        for (i, game) in self.players.iter_mut().enumerate() {
+            let garbage_sent = game.pos.garbage_sent;
+            over |= game.step(&mut searches[i], &mut nets[i], rules)?.is_none();
+            sent[i] = game.pos.garbage_sent - garbage_sent;
+        }
+
+        if over {
+            return Ok(false);
+        }
+
+        for (i, lines) in sent.into_iter().enumerate() {
+            let target = &mut self.players[1 - i];
+            let hole = self.holes[1 - i].gen_range(0..target.pos.width());
+            target.pos.receive_garbage(Garbage { lines, hole });
+        }
+
+        Ok(true)
+    }
+
+    /// Plays rounds until a player is out or both placed `max_pieces`.
+    pub fn play_out(
+        &mut self,
+        searches: &mut [Search; 2],
+        nets: &mut [FeedForwardNetwork; 2],
+        rules: &Rules,
+        max_pieces: usize,
+    ) -> Result<(), NetError> {
+        while self.players[0].pieces < max_pieces {
+            if !self.round(searches, nets, rules)? {
+                break;
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn summary(&self) -> VersusSummary {
+        let players = self.players.each_ref().map(Game::summary);
+        let winner = match players.map(|player| player.game_over.is_some()) {
+            [false, true] => Some(0),
+            [true, false] => Some(1),
+            _ => None,
+        };
+
+        VersusSummary {
+            seed: self.players[0].seed,
+            winner,
+            garbage_sent: self.players.each_ref().map(|game| game.pos.garbage_sent),
+            players,
+        }
+    }
+}
+
+/// Outcome of a match, `winner` being `None` for a draw: both players out
+/// in the same round, or neither at the piece limit.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct VersusSummary {
+    pub seed: u64,
+    pub winner: Option<usize>,
+    pub players: [GameSummary; 2],
+    pub garbage_sent: [usize; 2],
+}
+
+/// Plays one match from `start` per seed between `nets[0]` and `nets[1]`,
+/// spreading them over `options.threads` workers like `play_games`.
+pub fn play_matches(
+    start: &Position,
+    seeds: &[u64],
+    nets: [&FeedForwardNetwork; 2],
+    options: &Options,
+) -> Result<Vec<VersusSummary>, NetError> {
+    let start = game::start_position(start, options);
+    // Matches always trade garbage, whatever the option says
+    let rules = Rules {
+        garbage: true,
+        ..options.rules()
+    };
+
+    game::run_parallel(
+        seeds.len(),
+        options.threads,
+        |workers| {
+            let options = options.shared_by(2 * workers);
+            (
+                [
+                    Search::with_rules(&options, rules),
+                    Search::with_rules(&options, rules),
+                ],
+                nets.map(FeedForwardNetwork::clone),
+            )
+        },
+        |(searches, nets), i| {
+            let mut versus = Versus::new(start.clone(), seeds[i]);
+            versus.set_preview(options.previews);
+            versus
+                .play_out(searches, nets, &rules, options.max_pieces)
+                .map(|_| versus.summary())
+        },
+    )
+    .into_iter()
+    .collect()
+}
+
+/// Totals of one side over several matches.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct SideStats {
+    pub wins: usize,
+    pub losses: usize,
+    /// Garbage lines sent, after cancelling.
+    pub attack: usize,
+    /// Attack per placed piece, standing in for attack per minute as the
+    /// games have no clock.
+    pub attack_per_piece: f64,
+    /// Pieces placed per match.
+    pub survival: Stats,
+}
+
+/// Aggregate of several matches, by side.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct MatchStats {
+    pub draws: usize,
+    pub sides: [SideStats; 2],
+}
+
+impl MatchStats {
+    pub fn from_summaries(matches: &[VersusSummary]) -> Option<MatchStats> {
+        if matches.is_empty() {
+            return None;
+        }
+
+        let draws = matches.iter().filter(|m| m.winner.is_none()).count();
+        let side = |i: usize| {
+            let pieces: Vec<i64> = matches.iter().map(|m| m.players[i].pieces as i64).collect();
+            let attack = matches.iter().map(|m| m.garbage_sent[i]).sum();
+
+            SideStats {
+                wins: matches.iter().filter(|m| m.winner == Some(i)).count(),
+                losses: matches.iter().filter(|m| m.winner == Some(1 - i)).count(),
+                attack,
+                attack_per_piece: attack as f64 / pieces.iter().sum::<i64>().max(1) as f64,
+                survival: Stats::from_scores(&pieces).unwrap(),
+            }
+        };
+
+        Some(MatchStats {
+            draws,
+            sides: [side(0), side(1)],
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::net::tests::sample_net;
+    use crate::pos::GameOver;
+
+    fn options() -> Options {
+        Options {
+            max_depth: 2,
+            max_pieces: 12,
+            ..Options::default()
+        }
+    }
+
+    #[test]
+    fn test_same_seed_same_match() {
+        let start = Position::default();
+        let nets = [&sample_net(), &sample_net()];
+        let first = play_matches(&start, &[7, 8], nets, &options()).unwrap();
+
+        let options = Options {
+            threads: 2,
+            ..options()
+        };
+        assert_eq!(
+            first,
+            play_matches(&start, &[7, 8], nets, &options).unwrap()
+        );
+        // Equal players on equal pieces play equal games
+        assert_eq!(first[0].players[0], first[0].players[1]);
+        assert_eq!(first[0].winner, None);
+    }
+
+    #[test]
+    fn test_garbage_decides_match() {
+        let options = options();
+        let mut searches = [Search::new(&options), Search::new(&options)];
+        let mut nets = [sample_net(), sample_net()];
+        let mut versus = Versus::new(Position::default(), 3);
+
+        versus.players[1]
+            .pos
+            .receive_garbage(Garbage { lines: 30, hole: 0 });
+        versus
+            .play_out(&mut searches, &mut nets, &options.rules(), 100)
+            .unwrap();
+
+        let summary = versus.summary();
+        assert_eq!(summary.winner, Some(0));
+        assert_eq!(summary.players[1].game_over, Some(GameOver::TopOut));
+        assert!(summary.players[1].pieces < 5);
+    }
+
+    #[test]
+    fn test_match_stats() {
+        assert_eq!(MatchStats::from_summaries(&[]), None);
+
+        let summary = |winner, pieces: [usize; 2], garbage_sent| {
+            let player = |pieces| GameSummary {
+                seed: 0,
+                score: 0,
+                lines: 0,
+                level: 0,
+                pieces,
+                game_over: None,
+            };
+
+            VersusSummary {
+                seed: 0,
+                winner,
+                players: pieces.map(player),
+                garbage_sent,
+            }
+        };
+        let stats = MatchStats::from_summaries(&[
+            summary(Some(0), [40, 39], [12, 3]),
+            summary(None, [100, 100], [20, 20]),
+            summary(Some(0), [60, 60], [8, 1]),
+        ])
+        .unwrap();
+
+        assert_eq!(stats.draws, 1);
+        assert_eq!(stats.sides[0].wins, 2);
+        assert_eq!(stats.sides[1].wins, 0);
+        assert_eq!(stats.sides[1].losses, 2);
+        assert_eq!(stats.sides[0].attack, 40);
+        assert_eq!(stats.sides[0].attack_per_piece, 0.2);
+        assert_eq!(stats.sides[1].survival.min, 39);
+    }
+}
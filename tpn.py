@@ -6,6 +6,18 @@ class Position:
     current_piece: int
     next_piece: int
     score: int
+    queue: list[int] = None
+    hold_piece: int = 0
+    hold_used: bool = False
+    combo: int = 0
+    back_to_back: bool = False
+    lines: int = 0
+    level: int = 0
+    # Pending garbage as (lines, hole column) pairs, oldest first
+    pending_garbage: list[tuple[int, int]] = None
+    garbage_sent: int = 0
+    # Randomizer state, as in "nes:T" or "bag:OJS", None before TPN v2
+    randomizer: str = None
 
 piece_to_int = {
     'I': 1,
@@ -15,14 +27,24 @@ piece_to_int = {
     'S': 5,
     'T': 6,
     'Z': 7,
+    'G': 8,
 }
 
 
 def loads(s: str) -> Position:
-    board = [[0 for _ in range(10)] for _ in range(22)]
     tokens = s.split(" ")
+    # Version 2 starts with its version, version 1 with the board
+    if tokens[0] == 'v2':
+        tokens = tokens[1:]
 
+    # Boards other than 10x22 start with their size, as in "10x40:"
     board_tok = tokens[0]
+    width, height = 10, 22
+    if ':' in board_tok:
+        size, board_tok = board_tok.split(':', 1)
+        width, height = (int(n) for n in size.split('x'))
+
+    board = [[0 for _ in range(width)] for _ in range(height)]
     curr_x = 0
     curr_y = 0
 
@@ -37,7 +59,30 @@ def loads(s: str) -> Position:
             curr_x += 1
 
     current_piece = piece_to_int[tokens[1][0]]
-    next_piece = piece_to_int[tokens[2][0]]
+    queue = [] if tokens[2] == '-' else [piece_to_int.get(p, 0) for p in tokens[2]]
+    next_piece = queue[0] if queue else 0
     score = int(tokens[3])
 
-    return Position(board, current_piece, next_piece, score)
\ No newline at end of file
+    hold_piece = 0
+    hold_used = False
+    if len(tokens) > 4:
+        hold_tok = tokens[4]
+        hold_used = hold_tok.endswith('*')
+        hold_tok = hold_tok.rstrip('*')
+        if hold_tok != '-':
+            hold_piece = piece_to_int[hold_tok]
+
+    combo = int(tokens[5]) if len(tokens) > 5 else 0
+    back_to_back = len(tokens) > 6 and tokens[6] == '1'
+    lines = int(tokens[7]) if len(tokens) > 7 else 0
+    level = int(tokens[8]) if len(tokens) > 8 else 0
+
+    pending_garbage = []
+    if len(tokens) > 9 and tokens[9] != '-':
+        for garbage in tokens[9].split(','):
+            garbage_lines, hole = garbage.split('@')
+            pending_garbage.append((int(garbage_lines), int(hole)))
+    garbage_sent = int(tokens[10]) if len(tokens) > 10 else 0
+    randomizer = tokens[11] if len(tokens) > 11 else None
+
+    return Position(board, current_piece, next_piece, score, queue, hold_piece, hold_used, combo, back_to_back, lines, level, pending_garbage, garbage_sent, randomizer)
\ No newline at end of file